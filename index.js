@@ -8,13 +8,43 @@ let settings;
 let WIDTH = 160;
 let HEIGHT = 90;
 let MAX_SAMPLES = 256;
-let MAX_DEPTH = 8;
+// Russian roulette ends dim paths early, so this can be generous
+let MAX_DEPTH = 32;
+
+// 0 Mono, 1 Side-by-side, 2 Top-bottom, 3 Omni-directional equirect (top-bottom)
+let STEREO_MODE = 0;
+let IPD = 0.064;
+// 0 converges at the look at point
+let CONVERGENCE = 0.0;
+
+// Global fog, 0 density turns it off
+let FOG_DENSITY = 0.0;
+let FOG_ALBEDO = [0.8, 0.85, 0.9];
+// Henyey-Greenstein g, 0 is isotropic
+let FOG_ANISOTROPY = 0.0;
+
+// 0 Path tracing, 1 Normals, 2 Depth, 3 Albedo, 4 UV, 5 Object ID, 6 Ambient occlusion, 7 Bidirectional, 8 Photon mapping, 9 Metropolis
+let INTEGRATOR = 0;
+// Depth shown as white, 0 uses the distance to the look at point
+let DEPTH_RANGE = 0.0;
+
+// Ambient occlusion is shown while the camera moves, 0 radius looks out to infinity
+const MOVE_INTEGRATOR = 6;
+let AO_RADIUS = 1.0;
+let AO_SAMPLES = 4;
+
+// Photons per pass, the gather radius starts here and shrinks every pass
+let PHOTON_COUNT = 20000;
+let PHOTON_RADIUS = 0.05;
+
+// 0 Independent, 1 Stratified, 2 Halton, 3 Owen scrambled Sobol, 4 Blue noise
+let SAMPLER = 3;
+// Blue noise looks best at the 1 spp used while moving
+const MOVE_SAMPLER = 4;
 
 // Should be this if we can get the screen size
 WIDTH = window.innerWidth / 2;
 HEIGHT = window.innerHeight / 2;
-const MOVEWIDTH = WIDTH / 4;
-const MOVEHEIGHT = HEIGHT / 4;
 
 /* 
 * LAYOUT:
@@ -31,6 +61,21 @@ const MOVEHEIGHT = HEIGHT / 4;
 * 10 Texture changed
 * 11 User input, reset rendering
 * 12 Busy
+* 13 Stereo mode
+* 14 Interpupillary distance
+* 15 Convergence distance
+* 16 Fog density
+* 17 Fog albedo R
+* 18 Fog albedo G
+* 19 Fog albedo B
+* 20 Fog anisotropy
+* 21 Integrator
+* 22 Depth range
+* 23 AO radius
+* 24 AO samples
+* 25 Photon count
+* 26 Photon radius
+* 27 Sampler
 */
 
 let gl;
@@ -57,7 +102,7 @@ let prevMouseY = 0;
 async function initWasm() {
     wasmMemory = (await init()).memory;
     settings = (await init_settings()) / 4;
-    let length = 13;
+    let length = 28;
     i32View = new Int32Array(wasmMemory.buffer);
     f32View = new Float32Array(wasmMemory.buffer);
     i32View[settings + 0] = WIDTH;
@@ -67,6 +112,11 @@ async function initWasm() {
     i32View[settings + 10] = 0;
     i32View[settings + 11] = 0;
     i32View[settings + 12] = 0;
+    i32View[settings + 13] = STEREO_MODE;
+    i32View[settings + 21] = INTEGRATOR;
+    i32View[settings + 24] = AO_SAMPLES;
+    i32View[settings + 25] = PHOTON_COUNT;
+    i32View[settings + 27] = SAMPLER;
 
     f32View[settings + 4] = originX;
     f32View[settings + 5] = originY;
@@ -74,6 +124,16 @@ async function initWasm() {
     f32View[settings + 7] = lookAtX;
     f32View[settings + 8] = lookAtY;
     f32View[settings + 9] = lookAtZ;
+    f32View[settings + 14] = IPD;
+    f32View[settings + 15] = CONVERGENCE;
+    f32View[settings + 16] = FOG_DENSITY;
+    f32View[settings + 17] = FOG_ALBEDO[0];
+    f32View[settings + 18] = FOG_ALBEDO[1];
+    f32View[settings + 19] = FOG_ALBEDO[2];
+    f32View[settings + 20] = FOG_ANISOTROPY;
+    f32View[settings + 22] = DEPTH_RANGE;
+    f32View[settings + 23] = AO_RADIUS;
+    f32View[settings + 26] = PHOTON_RADIUS;
 }
 
 await initWasm();
@@ -92,12 +152,7 @@ async function runTracer() {
     trace();
     
     texturePointer = await get_texture();
-    if (orbit) {
-        webglSetup(MOVEWIDTH, MOVEHEIGHT, 1);
-    }
-    else {
-        webglSetup(WIDTH, HEIGHT, 0);
-    }
+    webglSetup(WIDTH, HEIGHT, 0);
 
     let samples = 0;
 
@@ -105,12 +160,7 @@ async function runTracer() {
         if (i32View[settings + 10] === 1) {
             i32View[settings + 10] = 0;
             texturePointer = await get_texture();
-            if (orbit | pan) {
-                webglSetup(MOVEWIDTH, MOVEHEIGHT, 1);
-            }
-            else {
-                webglSetup(WIDTH, HEIGHT, 0);
-            }
+            webglSetup(WIDTH, HEIGHT, 0);
             samples += 1;
             fpsDisplay.innerHTML = `Samples per second: ${1000 / ((performance.now() - timeStart) / samples)}`;
         }
@@ -189,9 +239,9 @@ document.addEventListener('click', (e) => e.preventDefault());
 document.addEventListener('mousedown', (e) => {
     prevMouseX = e.clientX;
     prevMouseY = e.clientY;
-    i32View[settings + 0] = MOVEWIDTH;
-    i32View[settings + 1] = MOVEHEIGHT;
     i32View[settings + 2] = 1;
+    i32View[settings + 21] = MOVE_INTEGRATOR;
+    i32View[settings + 27] = MOVE_SAMPLER;
 
     // Pan
     if (e.button === 0) {
@@ -213,9 +263,9 @@ document.addEventListener('mouseup', (e) => {
         orbit = false;
     }
 
-    i32View[settings + 0] = WIDTH;
-    i32View[settings + 1] = HEIGHT;
     i32View[settings + 2] = MAX_SAMPLES;
+    i32View[settings + 21] = INTEGRATOR;
+    i32View[settings + 27] = SAMPLER;
     i32View[settings + 11] = 1;
 
     runTracer();
@@ -0,0 +1,414 @@
+// Bidirectional path tracing, Veach's thesis by way of pbrt
+// Every camera subpath vertex is connected to every light subpath vertex and the results are
+// weighted with the balance heuristic. Light subpaths that connect straight to the camera (t = 1)
+// are left out, stereo and omni-directional cameras don't project back onto a pixel, so the
+// weights only count strategies with at least two camera vertices.
+// Materials without evaluate are treated as specular. Volumes, the global fog included, make
+// medium vertices, which have no cosine in their densities
+use core::f32;
+
+use crate::{integrator::{self, Fog, Integrator, LightList, MediumStack}, interval::Interval, object_list::object_list::ObjectList, ray::ray::Ray, scene_object::scene_object::{HitRecord, SceneObject}, vector3::Vector3};
+
+// Keeps connection rays off the surfaces at both ends
+const SHADOW_EPSILON: f32 = 0.001;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    point: Vector3,
+    // Outward on lights, towards the ray that found it on surfaces, unused on the camera and in media
+    normal: Vector3,
+    hit: Option<HitRecord>,
+    // Path throughput up to and including this vertex
+    beta: Vector3,
+    // Area densities of sampling this vertex from the start and from the end of the path
+    pdf_fwd: f32,
+    pdf_rev: f32,
+    delta: bool,
+    // Absorbing media the path reached this vertex through, connections from it go through them too
+    media: MediumStack,
+}
+
+impl Vertex {
+    fn camera(point: Vector3) -> Self {
+        return Self { kind: VertexKind::Camera, point, normal: Vector3::default(), hit: None, beta: Vector3::new(1.0, 1.0, 1.0), pdf_fwd: 0.0, pdf_rev: 0.0, delta: false, media: MediumStack::default() };
+    }
+
+    fn light(hit: HitRecord, beta: Vector3, pdf: f32) -> Self {
+        return Self { kind: VertexKind::Light, point: hit.point, normal: hit.normal, hit: Some(hit), beta, pdf_fwd: pdf, pdf_rev: 0.0, delta: false, media: MediumStack::default() };
+    }
+
+    // Or a medium, if the material is a phase function
+    fn surface(hit: HitRecord, beta: Vector3) -> Self {
+        let kind = if hit.material.is_phase_function() { VertexKind::Medium } else { VertexKind::Surface };
+        return Self { kind, point: hit.point, normal: hit.normal, hit: Some(hit), beta, pdf_fwd: 0.0, pdf_rev: 0.0, delta: false, media: MediumStack::default() };
+    }
+
+    fn is_emissive(&self) -> bool {
+        return self.hit.as_ref().is_some_and(|hit| hit.material.is_emissive());
+    }
+
+    fn emitted(&self) -> Vector3 {
+        return self.hit.as_ref().map_or(Vector3::default(), |hit| hit.material.emitted(hit));
+    }
+
+    // Solid angle density at this vertex to area density at next
+    fn convert_density(&self, pdf: f32, next: &Vertex) -> f32 {
+        let offset = next.point - self.point;
+        let distance_squared = offset.norm_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+
+        let mut pdf = pdf / distance_squared;
+        if next.kind == VertexKind::Surface || next.kind == VertexKind::Light {
+            pdf *= next.normal.dot(offset / distance_squared.sqrt()).abs();
+        }
+
+        return pdf;
+    }
+
+    // BSDF times cosine, zero for anything that can't be evaluated
+    fn f(&self, prev: Vector3, next: Vector3) -> Vector3 {
+        let Some(hit) = &self.hit else {
+            return Vector3::default();
+        };
+
+        let wo = (prev - self.point).normalize();
+        let wi = (next - self.point).normalize();
+
+        return hit.material.evaluate(hit, wo, wi).map_or(Vector3::default(), |(f, _)| f);
+    }
+
+    // Density of an emitter sending light towards next, emission is cosine weighted
+    fn pdf_light(&self, next: &Vertex) -> f32 {
+        let direction = (next.point - self.point).normalize();
+        let cos_theta = self.normal.dot(direction).max(0.0);
+
+        return self.convert_density(cos_theta / f32::consts::PI, next);
+    }
+
+    // Density of sampling next from this vertex, having arrived from prev
+    fn pdf(&self, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        if self.kind == VertexKind::Light {
+            return self.pdf_light(next);
+        }
+
+        let (Some(hit), Some(prev)) = (&self.hit, prev) else {
+            return 0.0;
+        };
+
+        let wo = (prev.point - self.point).normalize();
+        let wi = (next.point - self.point).normalize();
+
+        return match hit.material.evaluate(hit, wo, wi) {
+            Some((_, pdf)) => self.convert_density(pdf, next),
+            None => 0.0,
+        };
+    }
+}
+
+pub struct BdptIntegrator {
+    max_depth: u32,
+    lights: LightList,
+    fog: Option<Fog>,
+}
+
+impl BdptIntegrator {
+    pub fn new(max_depth: u32, fog: Option<Fog>) -> Self {
+        return Self { max_depth, lights: LightList::default(), fog };
+    }
+
+    fn sample_light(&self) -> Option<Vertex> {
+        let (hit, pdf) = self.lights.sample()?;
+        let emitted = hit.material.emitted(&hit);
+        return Some(Vertex::light(hit, emitted / pdf, pdf));
+    }
+
+    // Density of a light subpath starting at this point on an emitter
+    fn pdf_light_origin(&self, world: &ObjectList, vertex: &Vertex) -> f32 {
+        let Some(hit) = &vertex.hit else {
+            return 0.0;
+        };
+
+        let Some(object) = world.objects.get(hit.object_id as usize) else {
+            return 0.0;
+        };
+
+        return self.lights.pdf(object.as_ref());
+    }
+
+    // Extends the path until it misses, is absorbed or reaches max_vertices
+    // Returns the sky seen by a path that escapes, or that something other than a light absorbs like in the path tracer
+    fn random_walk(&self, world: &ObjectList, ray: Ray, beta: Vector3, pdf_dir: f32, path: &mut Vec<Vertex>, max_vertices: usize) -> Option<Vector3> {
+        let mut ray = ray;
+        let mut beta = beta;
+        let mut pdf_fwd = pdf_dir;
+        let mut media = MediumStack::default();
+
+        while path.len() < max_vertices {
+            let hit = world.hit(&ray, Interval::new(0.001, f32::INFINITY));
+
+            // Fog can scatter the ray before it reaches anything, including rays headed for the sky
+            let fog_event = self.fog.as_ref().and_then(|fog| {
+                let distance = fog.sample_distance();
+                (distance < hit.as_ref().map_or(f32::INFINITY, |hit| hit.t)).then(|| fog.event(&ray, distance))
+            });
+
+            let Some(hit) = fog_event.or(hit) else {
+                return Some(beta.component_mul(integrator::background(&ray)));
+            };
+
+            beta = beta.component_mul(media.transmittance(hit.t));
+
+            let material = hit.material.clone();
+            let mut vertex = Vertex::surface(hit, beta);
+            vertex.pdf_fwd = path.last().unwrap().convert_density(pdf_fwd, &vertex);
+            vertex.media = media;
+            path.push(vertex);
+
+            let current = path.len() - 1;
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let hit = path[current].hit.as_ref().unwrap();
+            let mut scattered = Ray::default();
+            let mut attenuation = Vector3::default();
+            if !material.scatter(&ray, hit, &mut attenuation, &mut scattered) {
+                if !material.is_emissive() {
+                    return Some(beta.component_mul(integrator::background(&ray)));
+                }
+
+                break;
+            }
+
+            // Both subpaths carry the film weight in their throughput
+            if let Some(weight) = integrator::carry_wavelength(&ray, &mut scattered) {
+                attenuation = attenuation.component_mul(weight);
+            }
+
+            media.update(hit, &scattered);
+
+            let wo = -1.0 * ray.direction();
+            let wi = scattered.direction();
+            let pdf_rev = match material.evaluate(hit, wo, wi) {
+                Some((_, pdf)) => {
+                    pdf_fwd = pdf;
+                    material.evaluate(hit, wi, wo).map_or(0.0, |(_, pdf)| pdf)
+                },
+                None => {
+                    path[current].delta = true;
+                    pdf_fwd = 0.0;
+                    0.0
+                },
+            };
+
+            path[current - 1].pdf_rev = path[current].convert_density(pdf_rev, &path[current - 1]);
+
+            beta = beta.component_mul(attenuation);
+            if beta == Vector3::default() {
+                break;
+            }
+
+            ray = scattered;
+        }
+
+        return None;
+    }
+
+    fn light_subpath(&self, world: &ObjectList, path: &mut Vec<Vertex>) {
+        let Some(light) = self.sample_light() else {
+            return;
+        };
+
+        let normal = light.normal;
+        let direction = integrator::cosine_direction(normal);
+        let pdf_dir = normal.dot(direction).max(0.0) / f32::consts::PI;
+        let ray = Ray::new(light.point, direction);
+        let beta = (normal.dot(direction) / pdf_dir) * light.beta;
+
+        path.push(light);
+
+        if pdf_dir > 0.0 {
+            self.random_walk(world, ray, beta, pdf_dir, path, self.max_depth as usize + 1);
+        }
+    }
+
+    // Fraction of light that gets from one point to the other, through the fog and the media from is in
+    fn visibility(&self, world: &ObjectList, from: &Vertex, to: Vector3) -> Vector3 {
+        let offset = to - from.point;
+        let distance = offset.norm();
+        let visible = world.transmittance(&Ray::new(from.point, offset / distance), Interval::new(SHADOW_EPSILON, distance - SHADOW_EPSILON));
+        let fog = self.fog.as_ref().map_or(1.0, |fog| fog.transmittance(distance));
+
+        return (visible * fog) * from.media.transmittance(distance);
+    }
+
+    // Unweighted contribution of the path made of s light and t camera vertices
+    fn connect(&self, world: &ObjectList, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize) -> Vector3 {
+        let pt = &camera_path[t - 1];
+        let mut sampled = None;
+
+        let radiance = match s {
+            // The camera subpath found a light by itself
+            0 => {
+                if !pt.is_emissive() {
+                    return Vector3::default();
+                }
+
+                pt.emitted().component_mul(pt.beta)
+            },
+            // Next event estimation with a fresh point on a light
+            1 => {
+                if pt.delta {
+                    return Vector3::default();
+                }
+
+                let Some(light) = self.sample_light() else {
+                    return Vector3::default();
+                };
+
+                let offset = pt.point - light.point;
+                let distance_squared = offset.norm_squared();
+                let cos_light = light.normal.dot(offset / distance_squared.sqrt()).max(0.0);
+                let f = pt.f(camera_path[t - 2].point, light.point);
+
+                let mut radiance = (cos_light / distance_squared) * pt.beta.component_mul(f).component_mul(light.beta);
+                if radiance != Vector3::default() {
+                    radiance = radiance.component_mul(self.visibility(world, pt, light.point));
+                }
+
+                sampled = Some(light);
+                radiance
+            },
+            _ => {
+                let qs = &light_path[s - 1];
+                if qs.delta || pt.delta {
+                    return Vector3::default();
+                }
+
+                let distance_squared = (pt.point - qs.point).norm_squared();
+                let f_light = qs.f(light_path[s - 2].point, pt.point);
+                let f_camera = pt.f(camera_path[t - 2].point, qs.point);
+
+                let mut radiance = (1.0 / distance_squared) * qs.beta.component_mul(f_light).component_mul(f_camera).component_mul(pt.beta);
+                if radiance != Vector3::default() {
+                    radiance = radiance.component_mul(self.visibility(world, pt, qs.point));
+                }
+
+                radiance
+            },
+        };
+
+        if radiance == Vector3::default() {
+            return radiance;
+        }
+
+        return self.mis_weight(world, light_path, camera_path, sampled.as_ref(), s, t) * radiance;
+    }
+
+    // Balance heuristic over every strategy that could have made the same path
+    fn mis_weight(&self, world: &ObjectList, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f32 {
+        let pt = &camera_path[t - 1];
+        let pt_minus = &camera_path[t - 2];
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light_path[s - 1]),
+        };
+        let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+
+        // (pdf_fwd, pdf_rev, delta) with the connection filled in
+        let mut camera: Vec<(f32, f32, bool)> = camera_path[..t].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        let mut light: Vec<(f32, f32, bool)> = light_path[..s.min(light_path.len())].iter().map(|v| (v.pdf_fwd, v.pdf_rev, v.delta)).collect();
+        if let Some(qs) = qs {
+            light.truncate(s - 1);
+            light.push((qs.pdf_fwd, qs.pdf_rev, false));
+        }
+
+        camera[t - 1].2 = false;
+        camera[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(qs_minus, pt),
+            None => self.pdf_light_origin(world, pt),
+        };
+
+        camera[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(Some(qs), pt_minus),
+            None => pt.pdf_light(pt_minus),
+        };
+
+        if let Some(qs) = qs {
+            light[s - 1].1 = pt.pdf(Some(pt_minus), qs);
+
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].1 = qs.pdf(Some(pt), qs_minus);
+            }
+        }
+
+        // Delta densities cancel out, so they count as one
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+
+        // Moving the connection towards the camera, stopping before t = 1
+        let mut ratio = 1.0;
+        for i in (2..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum += ratio;
+            }
+        }
+
+        // And towards the light
+        let mut ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let prev_delta = i > 0 && light[i - 1].2;
+            if !light[i].2 && !prev_delta {
+                sum += ratio;
+            }
+        }
+
+        return 1.0 / (1.0 + sum);
+    }
+}
+
+impl Integrator for BdptIntegrator {
+    fn preprocess(&mut self, world: &ObjectList) {
+        self.lights = LightList::new(world);
+    }
+
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        let max_vertices = self.max_depth as usize + 1;
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+
+        // The sky can't be sampled from the light side, so escaping paths keep all of it
+        let mut camera_path = vec![Vertex::camera(ray.origin())];
+        if let Some(sky) = self.random_walk(world, ray.clone(), Vector3::new(1.0, 1.0, 1.0), 1.0, &mut camera_path, max_vertices) {
+            radiance += sky;
+        }
+
+        let mut light_path = Vec::with_capacity(max_vertices);
+        self.light_subpath(world, &mut light_path);
+
+        for t in 2..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t - 2 > self.max_depth as usize {
+                    continue;
+                }
+
+                radiance += self.connect(world, &light_path, &camera_path, s, t);
+            }
+        }
+
+        return radiance;
+    }
+}
@@ -0,0 +1,172 @@
+// Tileable blue noise mask, made once with Ulichney's void and cluster method
+// Every value shows up once and neighbouring pixels end up far apart in value, so thresholding or
+// offsetting samples with it spreads the error as high frequency noise that the eye (and a blur) ignores
+use std::sync::OnceLock;
+
+use crate::rng::Xorshift32State;
+
+pub const SIZE: usize = 64;
+const SIGMA: f32 = 1.9;
+// Fraction of pixels in the starting pattern
+const INITIAL_DENSITY: f32 = 0.1;
+
+static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+
+// Slow, so it's made once at startup rather than by the first preview
+pub fn generate_mask() {
+    MASK.get_or_init(generate);
+}
+
+// Value in [0, 1) for a pixel, wraps around
+pub fn value(x: u32, y: u32) -> f32 {
+    let mask = MASK.get().expect("blue noise mask is made in init_settings");
+    return mask[(y as usize % SIZE) * SIZE + x as usize % SIZE];
+}
+
+// Energy of a point spread with a gaussian over the torus
+struct EnergyField {
+    kernel: Vec<f32>,
+    energy: Vec<f32>,
+}
+
+impl EnergyField {
+    fn new() -> Self {
+        let mut kernel = vec![0.0; SIZE * SIZE];
+        for dy in 0..SIZE {
+            for dx in 0..SIZE {
+                // Shortest way around
+                let x = dx.min(SIZE - dx) as f32;
+                let y = dy.min(SIZE - dy) as f32;
+                kernel[dy * SIZE + dx] = f32::exp(-(x * x + y * y) / (2.0 * SIGMA * SIGMA));
+            }
+        }
+
+        return Self { kernel, energy: vec![0.0; SIZE * SIZE] };
+    }
+
+    fn splat(&mut self, pixel: usize, sign: f32) {
+        let (px, py) = (pixel % SIZE, pixel / SIZE);
+
+        for y in 0..SIZE {
+            let dy = (y + SIZE - py) % SIZE;
+            for x in 0..SIZE {
+                let dx = (x + SIZE - px) % SIZE;
+                self.energy[y * SIZE + x] += sign * self.kernel[dy * SIZE + dx];
+            }
+        }
+    }
+
+    // Set pixel with the most energy around it
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        return (0..pattern.len())
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap();
+    }
+
+    // Empty pixel with the least
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        return (0..pattern.len())
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap();
+    }
+}
+
+fn generate() -> Vec<f32> {
+    let count = SIZE * SIZE;
+    let mut rng = Xorshift32State::new(0xB1E5EED);
+    let mut pattern = vec![false; count];
+    let mut field = EnergyField::new();
+
+    // Random starting points
+    let initial = (INITIAL_DENSITY * count as f32) as usize;
+    let mut placed = 0;
+    while placed < initial {
+        let pixel = ((rng.next_scalar() * count as f32) as usize).min(count - 1);
+        if !pattern[pixel] {
+            pattern[pixel] = true;
+            field.splat(pixel, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move points from clusters to voids until the one taken out is the best place to put it back
+    for _ in 0..count {
+        let cluster = field.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        field.splat(cluster, -1.0);
+
+        let void = field.largest_void(&pattern);
+        pattern[void] = true;
+        field.splat(void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; count];
+
+    // Ranks below the starting pattern, taking points away from the tightest clusters
+    let mut removing = pattern.clone();
+    let mut removing_field = EnergyField { kernel: field.kernel.clone(), energy: field.energy.clone() };
+    for r in (0..initial).rev() {
+        let cluster = removing_field.tightest_cluster(&removing);
+        removing[cluster] = false;
+        removing_field.splat(cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    // Ranks up to half full, filling the largest voids
+    let half = count / 2;
+    for r in initial..half {
+        let void = field.largest_void(&pattern);
+        pattern[void] = true;
+        field.splat(void, 1.0);
+        rank[void] = r;
+    }
+
+    // Past half the empty pixels are the minority, so fill the tightest cluster of them instead
+    let mut empty: Vec<bool> = pattern.iter().map(|&set| !set).collect();
+    let mut empty_field = EnergyField::new();
+    for pixel in (0..count).filter(|&i| empty[i]) {
+        empty_field.splat(pixel, 1.0);
+    }
+
+    for r in half.max(initial)..count {
+        let cluster = empty_field.tightest_cluster(&empty);
+        empty[cluster] = false;
+        empty_field.splat(cluster, -1.0);
+        rank[cluster] = r;
+    }
+
+    return rank.iter().map(|&r| (r as f32 + 0.5) / count as f32).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_rank_shows_up_once() {
+        generate_mask();
+        let count = SIZE * SIZE;
+        let mut histogram = vec![0; count];
+        for y in 0..SIZE as u32 {
+            for x in 0..SIZE as u32 {
+                let rank = (value(x, y) * count as f32) as usize;
+                assert!(rank < count);
+                histogram[rank] += 1;
+            }
+        }
+
+        assert!(histogram.iter().all(|&hits| hits == 1));
+    }
+
+    #[test]
+    fn value_wraps_around() {
+        generate_mask();
+        assert_eq!(value(3, 5).to_bits(), value(3 + SIZE as u32, 5 + 2 * SIZE as u32).to_bits());
+    }
+}
@@ -7,7 +7,27 @@ use wasm_bindgen_futures::JsFuture;
 use crate::SETTINGS;
 use crate::vector3::Vector3;
 
-use crate::{color, interval, object_list::object_list::ObjectList, ray::ray::Ray, rng, scene_object::scene_object::SceneObject, shared_mem::SharedMem, TEXTURE};
+use crate::{color, integrator::{self, Integrator, PathIntegrator}, object_list::object_list::ObjectList, ray::ray::{Ray, RayDifferential}, rng, sampler::{self, IndependentSampler, Sampler}, shared_mem::SharedMem, TEXTURE};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StereoMode {
+    Mono,
+    SideBySide,
+    TopBottom,
+    // Omni-directional stereo, both eyes as equirect panoramas stacked top-bottom
+    OmniDirectional,
+}
+
+impl StereoMode {
+    pub fn from_u32(mode: u32) -> Self {
+        return match mode {
+            1 => StereoMode::SideBySide,
+            2 => StereoMode::TopBottom,
+            3 => StereoMode::OmniDirectional,
+            _ => StereoMode::Mono,
+        };
+    }
+}
 
 pub struct Camera {
     #[allow(dead_code)]
@@ -15,17 +35,29 @@ pub struct Camera {
     pub image_width: u32,
     pub image_height: u32,
     pub samples_per_pixel: u32,
-    pub max_depth: u32,
 
     pub fov_vertical: f32,
     pub location: Vector3,
     pub look_at: Vector3,
     pub up: Vector3,
 
+    pub stereo_mode: StereoMode,
+    pub interpupillary_distance: f32,
+    // 0 or less converges at the look at point, unused by omni-directional stereo
+    pub convergence_distance: f32,
+
+    pub integrator: Box<dyn Integrator>,
+    // Installed as the random source for every pixel sample
+    pub sampler: Option<Box<dyn Sampler>>,
+
     camera_centre: Vector3,
-    pixel_00_loc: Vector3,
-    pixel_delta_u: Vector3, 
-    pixel_delta_v: Vector3,
+    // Left eye first, both are the camera centre in mono
+    eye_centres: [Vector3; 2],
+    // Odd sizes give the extra pixel to the right or bottom eye
+    eye_sizes: [(u32, u32); 2],
+    pixel_00_loc: [Vector3; 2],
+    pixel_delta_u: [Vector3; 2],
+    pixel_delta_v: [Vector3; 2],
     u: Vector3,
     v: Vector3,
     w: Vector3,
@@ -41,9 +73,13 @@ impl Camera {
             image_width: settings.target_width,
             image_height: settings.target_height,
             samples_per_pixel: settings.samples_per_pixel,
-            max_depth: settings.max_bounces,
             location: Vector3::new(settings.origin_x, settings.origin_y, settings.origin_z),
             look_at: Vector3::new(settings.look_at_x, settings.look_at_y, settings.look_at_z),
+            stereo_mode: StereoMode::from_u32(settings.stereo_mode),
+            interpupillary_distance: settings.interpupillary_distance,
+            convergence_distance: settings.convergence_distance,
+            integrator: integrator::from_settings(settings),
+            sampler: Some(sampler::from_settings(settings)),
             ..Default::default()
         }
     }
@@ -61,16 +97,35 @@ impl Camera {
                 break;
             }
 
-            for row in 0..self.image_height {
-                for col in 0..self.image_width {
-                    let mut pixel_color = Vector3::new(0.0, 0.0, 0.0);
-                    let ray = self.get_ray(col, row);
-                    pixel_color += Self::ray_color(&ray, &world, self.max_depth);
-                    // Write accumulated texture here, before gamma correction
-                    color::write_color(pixel_color, &mut self.reservoir, ((self.image_width * ((self.image_height - 1) - row) + col) * 3) as usize);
+            self.integrator.preprocess(world);
+
+            let mut reservoir = std::mem::take(&mut self.reservoir);
+            let (width, height) = (self.image_width, self.image_height);
+            let mut splat = |col: u32, row: u32, color: Vector3| {
+                color::write_color(color, &mut reservoir, ((width * ((height - 1) - row) + col) * 3) as usize);
+            };
+
+            if !self.integrator.render_pass(world, width, height, &|col, row| self.get_ray(col, row), &mut splat) {
+                let mut sampler = self.sampler.take().unwrap_or_else(|| Box::new(IndependentSampler::new()));
+
+                for row in 0..self.image_height {
+                    for col in 0..self.image_width {
+                        sampler.start_pixel_sample(col, row, self.sample_count);
+                        let pixel_color = rng::with_source(&mut sampler, || {
+                            let ray = self.get_ray(col, row);
+                            self.integrator.ray_color(&ray, &world)
+                        });
+
+                        // Write accumulated texture here, before gamma correction
+                        splat(col, row, pixel_color);
+                    }
                 }
+
+                self.sampler = Some(sampler);
             }
 
+            self.reservoir = reservoir;
+
             // Done rendering the first sample, now gamma correct and clone
             self.sample_count += 1;
             color::gamma_correct_average(&mut self.temp_texture, &self.reservoir, self.sample_count);
@@ -87,16 +142,72 @@ impl Camera {
     }
 
     fn get_ray(&self, i:u32, j:u32) -> Ray {
+        let (eye, i, j) = self.eye_pixel(i, j);
         let offset = Self::sample_square();
-        let pixel_sample = self.pixel_00_loc + ((i as f32 + offset.x()) * self.pixel_delta_u)
-                            + ((j as f32 + offset.y()) * self.pixel_delta_v);
+        let x = i as f32 + 0.5 + offset.x();
+        let y = j as f32 + 0.5 + offset.y();
+
+        let ray = self.get_eye_ray(eye, x, y);
+
+        // Rays through the next pixel over, for texture filtering
+        let ray_x = self.get_eye_ray(eye, x + 1.0, y);
+        let ray_y = self.get_eye_ray(eye, x, y + 1.0);
+        let differentials = RayDifferential {
+            rx_origin: ray_x.origin(),
+            rx_direction: ray_x.direction(),
+            ry_origin: ray_y.origin(),
+            ry_direction: ray_y.direction(),
+        };
+
+        return Ray::with_differentials(ray.origin(), ray.direction(), Some(differentials));
+    }
+
+    // x and y are continuous pixel coordinates within the eye's view
+    fn get_eye_ray(&self, eye: usize, x: f32, y: f32) -> Ray {
+        if self.stereo_mode == StereoMode::OmniDirectional {
+            return self.get_ods_ray(eye, x, y);
+        }
 
-        let ray_origin = self.camera_centre;
+        // Both eyes share the viewport at the convergence distance, so each eye gets an off-axis frustum
+        let pixel_sample = self.pixel_00_loc[eye] + ((x - 0.5) * self.pixel_delta_u[eye])
+                            + ((y - 0.5) * self.pixel_delta_v[eye]);
+
+        let ray_origin = self.eye_centres[eye];
         let ray_direction = (pixel_sample - ray_origin).normalize();
 
         return Ray::new(ray_origin, ray_direction);
     }
 
+    // Maps a texture pixel to its eye and the pixel within that eye's view
+    // Left eye is on the left or on top
+    fn eye_pixel(&self, i: u32, j: u32) -> (usize, u32, u32) {
+        let (left_width, left_height) = self.eye_sizes[0];
+
+        return match self.stereo_mode {
+            StereoMode::Mono => (0, i, j),
+            StereoMode::SideBySide => if i < left_width { (0, i, j) } else { (1, i - left_width, j) },
+            StereoMode::TopBottom | StereoMode::OmniDirectional => if j < left_height { (0, i, j) } else { (1, i, j - left_height) },
+        };
+    }
+
+    // Equirect ray for omni-directional stereo
+    // Each eye sits on a circle with a diameter of the ipd, and every ray starts where its direction
+    // is tangent to that circle. Directions stay parallel between the eyes, there's no toe in
+    fn get_ods_ray(&self, eye: usize, x: f32, y: f32) -> Ray {
+        let (eye_width, eye_height) = self.eye_sizes[eye];
+        let theta = (x / eye_width as f32) * 2.0 * f32::consts::PI - f32::consts::PI;
+        let phi = f32::consts::FRAC_PI_2 - (y / eye_height as f32) * f32::consts::PI;
+
+        let forward = -1.0 * self.w;
+        let direction = f32::cos(phi) * (f32::sin(theta) * self.u + f32::cos(theta) * forward) + f32::sin(phi) * self.v;
+        let right = f32::cos(theta) * self.u - f32::sin(theta) * forward;
+
+        let eye_sign = if eye == 0 { -1.0 } else { 1.0 };
+        let ray_origin = self.camera_centre + (eye_sign * 0.5 * self.interpupillary_distance) * right;
+
+        return Ray::new(ray_origin, direction.normalize());
+    }
+
     fn sample_square() -> Vector3 {
         // return Vector3::new(0.5, 0.5, 0.0);
         return Vector3::new(rng::random_f32() - 0.5, rng::random_f32() - 0.5, 0.0);
@@ -105,11 +216,22 @@ impl Camera {
     fn initialise(&mut self) {
         self.camera_centre = self.location;
         let focal_length = (self.location - self.look_at).norm();
+        if self.convergence_distance <= 0.0 {
+            self.convergence_distance = focal_length;
+        }
+
+        let (width, height) = (self.image_width, self.image_height);
+        self.eye_sizes = match self.stereo_mode {
+            StereoMode::Mono => [(width, height); 2],
+            StereoMode::SideBySide => [(width / 2, height), (width - width / 2, height)],
+            StereoMode::TopBottom | StereoMode::OmniDirectional => [(width, height / 2), (width, height - height / 2)],
+        };
+
         let theta = self.fov_vertical.to_radians();
         let h = f32::tan(theta/2.0);
 
-        let viewport_height = 2.0 * h * focal_length;
-        let viewport_width = viewport_height * ((self.image_width as f32)/self.image_height as f32);
+        // Viewport sits at the convergence distance, which is the focal length in mono
+        let viewport_height = 2.0 * h * self.convergence_distance;
 
         self.reservoir = vec![0f32; self.image_width as usize * self.image_height as usize * 3];
         self.temp_texture = vec![0u8; self.image_width as usize * self.image_height as usize * 3];
@@ -118,35 +240,23 @@ impl Camera {
         self.u = (self.up.cross(self.w)).normalize();
         self.v = self.w.cross(self.u);
 
-        let viewport_u = viewport_width * self.u;
-        let viewport_v = viewport_height * -1.0 * self.v;
+        let half_ipd = if self.stereo_mode == StereoMode::Mono { 0.0 } else { 0.5 * self.interpupillary_distance };
+        self.eye_centres = [self.camera_centre - half_ipd * self.u, self.camera_centre + half_ipd * self.u];
 
-        self.pixel_delta_u = viewport_u / Vector3::new(self.image_width as f32, self.image_width as f32, self.image_width as f32);
-        self.pixel_delta_v = viewport_v / Vector3::new(self.image_height as f32, self.image_height as f32, self.image_height as f32);
+        // Each eye gets its own pixel grid in case the split was uneven
+        for eye in 0..2 {
+            let (eye_width, eye_height) = self.eye_sizes[eye];
+            let viewport_width = viewport_height * ((eye_width as f32)/eye_height as f32);
 
+            let viewport_u = viewport_width * self.u;
+            let viewport_v = viewport_height * -1.0 * self.v;
 
-        let viewport_upper_left = self.camera_centre - (focal_length * self.w) - viewport_u/2.0 - viewport_v/2.0; 
-        self.pixel_00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);        
-    }
+            self.pixel_delta_u[eye] = viewport_u / Vector3::new(eye_width as f32, eye_width as f32, eye_width as f32);
+            self.pixel_delta_v[eye] = viewport_v / Vector3::new(eye_height as f32, eye_height as f32, eye_height as f32);
 
-    fn ray_color(ray: &Ray, world: &ObjectList, depth: u32) -> Vector3 {
-        if depth <= 0 {
-            return Vector3::new(0.0, 0.0, 0.0);
+            let viewport_upper_left = self.camera_centre - (self.convergence_distance * self.w) - viewport_u/2.0 - viewport_v/2.0; 
+            self.pixel_00_loc[eye] = viewport_upper_left + 0.5 * (self.pixel_delta_u[eye] + self.pixel_delta_v[eye]);
         }
-
-        if let Some(hit) = world.hit(ray, interval::Interval::new(0.001, std::f32::INFINITY)) {
-            // let direction = hit.normal + vector_utils::random_vec3_unit();
-            // return 0.5 * Self::ray_color(&Ray::new(hit.point, direction), world, depth - 1);
-            let mut scattered: Ray = Ray::default();
-            let mut attenuation = Vector3::default();
-
-            if hit.material.scatter(ray, &hit, &mut attenuation, &mut scattered) {
-                return Self::ray_color(&scattered, world, depth-1).component_mul(attenuation);
-            }
-        }
-
-        let a = 0.5*ray.direction().y() + 1.0;
-        return (1.0-a)*Vector3::new(1.0, 1.0, 1.0) + a*Vector3::new(0.5, 0.7, 1.0);
     }
 }
 
@@ -160,10 +270,14 @@ impl Default for Camera {
             reservoir: vec![0f32; 0 as usize * 0 as usize * 3],
             temp_texture: vec![0u8; 0 as usize * 0 as usize * 3],
             camera_centre: Vector3::new(0.0, 0.0, 0.0),
-            pixel_00_loc: Vector3::new(0.0, 0.0, 0.0),
-            pixel_delta_u: Vector3::new(0.0, 0.0, 0.0),
-            pixel_delta_v: Vector3::new(0.0, 0.0, 0.0),
-            max_depth: 8,
+            eye_centres: [Vector3::new(0.0, 0.0, 0.0); 2],
+            eye_sizes: [(0, 0); 2],
+            stereo_mode: StereoMode::Mono,
+            interpupillary_distance: 0.064,
+            convergence_distance: 0.0,
+            pixel_00_loc: [Vector3::new(0.0, 0.0, 0.0); 2],
+            pixel_delta_u: [Vector3::new(0.0, 0.0, 0.0); 2],
+            pixel_delta_v: [Vector3::new(0.0, 0.0, 0.0); 2],
             fov_vertical: 90.0,
             location: Vector3::new(0.0, 0.0, 0.0),
             look_at: Vector3::new(0.0, 0.0, -1.0),
@@ -172,6 +286,8 @@ impl Default for Camera {
             v: Vector3::new(0.0, 0.0, 0.0),
             w: Vector3::new(0.0, 0.0, 0.0),
             sample_count: 0,
+            integrator: Box::new(PathIntegrator::new(8, None)),
+            sampler: None,
         }
     }
-}
\ No newline at end of file
+}
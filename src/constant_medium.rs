@@ -0,0 +1,76 @@
+pub mod constant_medium {
+    use std::sync::Arc;
+
+    use crate::interval::Interval;
+    use crate::material::Material;
+    use crate::ray::ray;
+    use crate::rng;
+    use crate::scene_object::scene_object::{HitRecord, SceneObject};
+    use crate::vector3::Vector3;
+
+    // Smoke and fog, a volume of constant density inside any closed boundary
+    // Same as the second book, so it assumes the boundary is convex
+    pub struct ConstantMedium {
+        boundary: Arc<dyn SceneObject>,
+        neg_inv_density: f32,
+        phase_function: Arc<dyn Material>,
+    }
+
+    impl ConstantMedium {
+        pub fn new(boundary: Arc<dyn SceneObject>, density: f32, phase_function: Arc<dyn Material>) -> Self {
+            ConstantMedium {
+                boundary,
+                neg_inv_density: -1.0 / density,
+                phase_function,
+            }
+        }
+    }
+
+    impl SceneObject for ConstantMedium {
+        fn hit(&self, ray: &ray::Ray, ray_t: Interval) -> Option<HitRecord> {
+            // Both crossings of the boundary, even if the ray starts inside
+            let entry = self.boundary.hit(ray, Interval::new(f32::NEG_INFINITY, f32::INFINITY))?;
+            let exit = self.boundary.hit(ray, Interval::new(entry.t + 0.0001, f32::INFINITY))?;
+
+            let t_min = entry.t.max(ray_t.min).max(0.0);
+            let t_max = exit.t.min(ray_t.max);
+
+            if t_min >= t_max {
+                return None;
+            }
+
+            // Direction is already normalised
+            let distance_inside_boundary = t_max - t_min;
+            let hit_distance = self.neg_inv_density * f32::ln(rng::random_f32());
+
+            if hit_distance > distance_inside_boundary {
+                return None;
+            }
+
+            let mut hit_record = HitRecord::new(self.phase_function.clone());
+            hit_record.t = t_min + hit_distance;
+            hit_record.point = ray.at(hit_record.t);
+
+            // Both are arbitrary inside a volume
+            hit_record.normal = Vector3::new(1.0, 0.0, 0.0);
+            hit_record.front_face = true;
+
+            return Some(hit_record);
+        }
+
+        // Beer-Lambert over the part of the ray inside the boundary
+        fn transmittance(&self, ray: &ray::Ray, ray_t: Interval) -> f32 {
+            let Some(entry) = self.boundary.hit(ray, Interval::new(f32::NEG_INFINITY, f32::INFINITY)) else { return 1.0; };
+            let Some(exit) = self.boundary.hit(ray, Interval::new(entry.t + 0.0001, f32::INFINITY)) else { return 1.0; };
+
+            let t_min = entry.t.max(ray_t.min).max(0.0);
+            let t_max = exit.t.min(ray_t.max);
+
+            if t_min >= t_max {
+                return 1.0;
+            }
+
+            return f32::exp((t_max - t_min) / self.neg_inv_density);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+pub mod heterogeneous_medium {
+    use std::sync::Arc;
+
+    use wasm_bindgen::prelude::*;
+
+    use crate::interval::Interval;
+    use crate::material::Material;
+    use crate::perlin::Perlin;
+    use crate::ray::ray;
+    use crate::rng::{self, Xorshift32State};
+    use crate::scene_object::scene_object::{HitRecord, SceneObject};
+    use crate::vector3::Vector3;
+
+    // Box, density scale and phase function shared by grid and noise volumes
+    #[wasm_bindgen]
+    #[derive(Clone, Copy)]
+    pub struct VolumeParams {
+        pub min_x: f32,
+        pub min_y: f32,
+        pub min_z: f32,
+        pub max_x: f32,
+        pub max_y: f32,
+        pub max_z: f32,
+        // Multiplies whatever the grid or noise gives
+        pub density: f32,
+        pub albedo_r: f32,
+        pub albedo_g: f32,
+        pub albedo_b: f32,
+        // Henyey-Greenstein g, negative scatters back, positive scatters forward
+        pub anisotropy: f32,
+    }
+
+    #[wasm_bindgen]
+    impl VolumeParams {
+        // Unit cube of white isotropic smoke
+        #[wasm_bindgen(constructor)]
+        pub fn new() -> Self {
+            return Self {
+                min_x: -0.5,
+                min_y: -0.5,
+                min_z: -0.5,
+                max_x: 0.5,
+                max_y: 0.5,
+                max_z: 0.5,
+                density: 1.0,
+                albedo_r: 1.0,
+                albedo_g: 1.0,
+                albedo_b: 1.0,
+                anisotropy: 0.0,
+            };
+        }
+    }
+
+    impl Default for VolumeParams {
+        fn default() -> Self {
+            return Self::new();
+        }
+    }
+
+    pub trait DensityField: Sync + Send {
+        fn density(&self, point: Vector3) -> f32;
+        // Majorant for delta tracking, density may never go above it
+        fn max_density(&self) -> f32;
+    }
+
+    // Densities on a regular grid over a box, x varies fastest
+    pub struct GridDensity {
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        min: Vector3,
+        extent: Vector3,
+        data: Vec<f32>,
+        max: f32,
+    }
+
+    impl GridDensity {
+        pub fn new(nx: u32, ny: u32, nz: u32, min: Vector3, max: Vector3, data: &[f32], scale: f32) -> Self {
+            let count = nx as usize * ny as usize * nz as usize;
+            let mut data: Vec<f32> = data.iter().take(count).map(|d| scale * d.max(0.0)).collect();
+            // Missing data is empty space
+            data.resize(count, 0.0);
+
+            let max_value = data.iter().cloned().fold(0.0, f32::max);
+
+            return Self {
+                nx: nx as usize,
+                ny: ny as usize,
+                nz: nz as usize,
+                min,
+                extent: max - min,
+                data,
+                max: max_value,
+            };
+        }
+
+        fn voxel(&self, i: i32, j: i32, k: i32) -> f32 {
+            let i = i.clamp(0, self.nx as i32 - 1) as usize;
+            let j = j.clamp(0, self.ny as i32 - 1) as usize;
+            let k = k.clamp(0, self.nz as i32 - 1) as usize;
+
+            return self.data[(k * self.ny + j) * self.nx + i];
+        }
+    }
+
+    impl DensityField for GridDensity {
+        // Trilinear, voxel centres are at half coordinates
+        fn density(&self, point: Vector3) -> f32 {
+            if self.data.is_empty() {
+                return 0.0;
+            }
+
+            let local = (point - self.min) / self.extent;
+            let x = local.x() * self.nx as f32 - 0.5;
+            let y = local.y() * self.ny as f32 - 0.5;
+            let z = local.z() * self.nz as f32 - 0.5;
+            let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+            let (dx, dy, dz) = (x - x0, y - y0, z - z0);
+            let (i, j, k) = (x0 as i32, y0 as i32, z0 as i32);
+
+            let lerp = |a: f32, b: f32, t: f32| (1.0 - t) * a + t * b;
+
+            let d00 = lerp(self.voxel(i, j, k), self.voxel(i + 1, j, k), dx);
+            let d10 = lerp(self.voxel(i, j + 1, k), self.voxel(i + 1, j + 1, k), dx);
+            let d01 = lerp(self.voxel(i, j, k + 1), self.voxel(i + 1, j, k + 1), dx);
+            let d11 = lerp(self.voxel(i, j + 1, k + 1), self.voxel(i + 1, j + 1, k + 1), dx);
+
+            return lerp(lerp(d00, d10, dy), lerp(d01, d11, dy), dz);
+        }
+
+        fn max_density(&self) -> f32 {
+            return self.max;
+        }
+    }
+
+    // Clouds from Perlin turbulence
+    pub struct NoiseDensity {
+        noise: Perlin,
+        scale: f32,
+        density: f32,
+    }
+
+    impl NoiseDensity {
+        pub fn new(scale: f32, density: f32, seed: u32) -> Self {
+            let mut rng = Xorshift32State::seeded(seed);
+            return Self { noise: Perlin::new(&mut rng), scale, density };
+        }
+    }
+
+    impl DensityField for NoiseDensity {
+        fn density(&self, point: Vector3) -> f32 {
+            // Clamped so max_density holds
+            return self.density * self.noise.turbulence(self.scale * point, 7).min(1.0);
+        }
+
+        fn max_density(&self) -> f32 {
+            return self.density;
+        }
+    }
+
+    // Volume with varying density inside a box, sampled with delta tracking
+    pub struct HeterogeneousMedium {
+        min: Vector3,
+        max: Vector3,
+        density: Arc<dyn DensityField>,
+        phase_function: Arc<dyn Material>,
+    }
+
+    impl HeterogeneousMedium {
+        pub fn new(min: Vector3, max: Vector3, density: Arc<dyn DensityField>, phase_function: Arc<dyn Material>) -> Self {
+            HeterogeneousMedium {
+                min,
+                max,
+                density,
+                phase_function,
+            }
+        }
+
+        // Slab test, gives the part of ray_t inside the box
+        fn clip(&self, ray: &ray::Ray, ray_t: &Interval) -> Option<(f32, f32)> {
+            let origin = ray.origin();
+            let direction = ray.direction();
+            let mut t_min = ray_t.min.max(0.0);
+            let mut t_max = ray_t.max;
+
+            for (o, d, lo, hi) in [
+                (origin.x(), direction.x(), self.min.x(), self.max.x()),
+                (origin.y(), direction.y(), self.min.y(), self.max.y()),
+                (origin.z(), direction.z(), self.min.z(), self.max.z()),
+            ] {
+                let inv_d = 1.0 / d;
+                let t0 = (lo - o) * inv_d;
+                let t1 = (hi - o) * inv_d;
+                let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+                t_min = t_min.max(t0);
+                t_max = t_max.min(t1);
+            }
+
+            if t_min >= t_max {
+                return None;
+            }
+
+            return Some((t_min, t_max));
+        }
+
+        // Distance to the next tentative collision against the majorant
+        fn step(&self) -> f32 {
+            return -f32::ln(1.0 - rng::random_f32()) / self.density.max_density();
+        }
+    }
+
+    impl SceneObject for HeterogeneousMedium {
+        // Delta tracking, real collisions scatter and null ones carry on
+        fn hit(&self, ray: &ray::Ray, ray_t: Interval) -> Option<HitRecord> {
+            let (t_min, t_max) = self.clip(ray, &ray_t)?;
+
+            if self.density.max_density() <= 0.0 {
+                return None;
+            }
+
+            let mut t = t_min;
+            loop {
+                t += self.step();
+                if t >= t_max {
+                    return None;
+                }
+
+                let point = ray.at(t);
+                if rng::random_f32() < self.density.density(point) / self.density.max_density() {
+                    let mut hit_record = HitRecord::new(self.phase_function.clone());
+                    hit_record.t = t;
+                    hit_record.point = point;
+
+                    // Both are arbitrary inside a volume
+                    hit_record.normal = Vector3::new(1.0, 0.0, 0.0);
+                    hit_record.front_face = true;
+
+                    return Some(hit_record);
+                }
+            }
+        }
+
+        // Ratio tracking, unbiased and smoother than counting delta tracking hits
+        fn transmittance(&self, ray: &ray::Ray, ray_t: Interval) -> f32 {
+            let Some((t_min, t_max)) = self.clip(ray, &ray_t) else { return 1.0; };
+
+            if self.density.max_density() <= 0.0 {
+                return 1.0;
+            }
+
+            let mut transmittance = 1.0;
+            let mut t = t_min;
+            loop {
+                t += self.step();
+                if t >= t_max {
+                    return transmittance;
+                }
+
+                transmittance *= 1.0 - self.density.density(ray.at(t)) / self.density.max_density();
+            }
+        }
+    }
+}
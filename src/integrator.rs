@@ -0,0 +1,568 @@
+// Integrators turn a camera ray into radiance, the camera only deals with pixels
+use core::f32;
+use std::sync::Arc;
+
+use crate::{bdpt::BdptIntegrator, interval::Interval, material::{HenyeyGreenstein, Material}, mlt::MltIntegrator, object_list::object_list::ObjectList, photon_map::PhotonMappingIntegrator, ray::ray::Ray, rng, scene_object::scene_object::{HitRecord, SceneObject}, shared_mem::SharedMem, spectrum, vector3::Vector3, vector_utils};
+
+// Bounces before Russian roulette can end a path
+const ROULETTE_MIN_BOUNCES: u32 = 3;
+// Even the dimmest paths get a chance, so the survivors' weights stay bounded
+const ROULETTE_MIN_SURVIVAL: f32 = 0.05;
+
+// Chance a path keeps going after roulette, by its brightest channel
+fn survival_probability(throughput: Vector3) -> f32 {
+    return throughput.x().max(throughput.y()).max(throughput.z()).clamp(ROULETTE_MIN_SURVIVAL, 1.0);
+}
+
+pub trait Integrator: Sync + Send {
+    // Called before every pass over the image
+    fn preprocess(&mut self, _world: &ObjectList) {}
+
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3;
+
+    // Integrators that pick their own pixels draw the whole pass here and return true
+    // get_ray gives a ray through a pixel, splat adds to one, each pass should add one sample per pixel on average
+    fn render_pass(&self, _world: &ObjectList, _width: u32, _height: u32, _get_ray: &dyn Fn(u32, u32) -> Ray, _splat: &mut dyn FnMut(u32, u32, Vector3)) -> bool {
+        return false;
+    }
+}
+
+// Picks the integrator from the settings
+pub fn from_settings(settings: &SharedMem) -> Box<dyn Integrator> {
+    let fog = if settings.fog_density > 0.0 {
+        Some(Fog::new(settings.fog_density, Vector3::new(settings.fog_albedo_r, settings.fog_albedo_g, settings.fog_albedo_b), settings.fog_anisotropy))
+    } else {
+        None
+    };
+
+    let aov = match settings.integrator {
+        1 => Aov::Normal,
+        2 => Aov::Depth,
+        3 => Aov::Albedo,
+        4 => Aov::Uv,
+        5 => Aov::ObjectId,
+        6 => return Box::new(AmbientOcclusionIntegrator::new(settings.ao_radius, settings.ao_samples)),
+        7 => return Box::new(BdptIntegrator::new(settings.max_bounces, fog)),
+        8 => return Box::new(PhotonMappingIntegrator::new(settings.max_bounces, settings.photon_count, settings.photon_radius)),
+        9 => return Box::new(MltIntegrator::new(PathIntegrator::new(settings.max_bounces, fog))),
+        _ => return Box::new(PathIntegrator::new(settings.max_bounces, fog)),
+    };
+
+    let origin = Vector3::new(settings.origin_x, settings.origin_y, settings.origin_z);
+    let look_at = Vector3::new(settings.look_at_x, settings.look_at_y, settings.look_at_z);
+    let depth_range = if settings.depth_range > 0.0 { settings.depth_range } else { (look_at - origin).norm() };
+
+    return Box::new(AovIntegrator::new(aov, look_at - origin, depth_range));
+}
+
+// Emissive objects for integrators that start paths on lights
+// One is picked uniformly, then a point uniformly over its area
+#[derive(Default)]
+pub struct LightList {
+    lights: Vec<Arc<dyn SceneObject>>,
+}
+
+impl LightList {
+    pub fn new(world: &ObjectList) -> Self {
+        let lights = world.objects.iter()
+            .filter(|object| object.area() > 0.0 && object.sample_surface().is_some_and(|hit| hit.material.is_emissive()))
+            .cloned()
+            .collect();
+
+        return Self { lights };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.lights.is_empty();
+    }
+
+    // Point on a light and its area density
+    pub fn sample(&self) -> Option<(HitRecord, f32)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+
+        let index = ((rng::random_f32() * self.lights.len() as f32) as usize).min(self.lights.len() - 1);
+        let light = &self.lights[index];
+        let hit = light.sample_surface()?;
+
+        return Some((hit, 1.0 / (self.lights.len() as f32 * light.area())));
+    }
+
+    // Area density of sample landing on this object
+    pub fn pdf(&self, object: &dyn SceneObject) -> f32 {
+        if self.lights.is_empty() || object.area() <= 0.0 {
+            return 0.0;
+        }
+
+        return 1.0 / (self.lights.len() as f32 * object.area());
+    }
+}
+
+// Cosine weighted direction around the normal, the same way Lambertian scatters
+pub fn cosine_direction(normal: Vector3) -> Vector3 {
+    let direction = normal + vector_utils::random_vec3_unit();
+    if vector_utils::near_zero(direction) {
+        return normal;
+    }
+
+    return direction.normalize();
+}
+
+// Once a path has picked a wavelength it keeps it
+// Returns the film weight for the wavelength when this bounce is the one that picked it
+pub fn carry_wavelength(ray: &Ray, scattered: &mut Ray) -> Option<Vector3> {
+    if ray.wavelength().is_some() {
+        scattered.set_wavelength(ray.wavelength());
+        return None;
+    }
+
+    return scattered.wavelength().map(spectrum::wavelength_to_rgb);
+}
+
+// Sky gradient, the only light in the scene
+pub fn background(ray: &Ray) -> Vector3 {
+    let a = 0.5*ray.direction().y() + 1.0;
+    return (1.0-a)*Vector3::new(1.0, 1.0, 1.0) + a*Vector3::new(0.5, 0.7, 1.0);
+}
+
+// Deepest nesting that keeps its own absorption, deeper media use the last one kept
+const MAX_NESTED_MEDIA: usize = 4;
+
+// Absorbing interiors a path is inside of, innermost last, so an air bubble in red glass is clear
+// and the glass around it is still red. Assumes surfaces don't overlap
+#[derive(Clone, Copy, Default)]
+pub struct MediumStack {
+    absorption: [Vector3; MAX_NESTED_MEDIA],
+    depth: usize,
+}
+
+impl MediumStack {
+    // Beer-Lambert over a segment of the innermost medium
+    pub fn transmittance(&self, distance: f32) -> Vector3 {
+        if self.depth == 0 {
+            return Vector3::new(1.0, 1.0, 1.0);
+        }
+
+        let absorption = self.absorption[self.depth.min(MAX_NESTED_MEDIA) - 1];
+        return Vector3::new(
+            f32::exp(-absorption.x() * distance),
+            f32::exp(-absorption.y() * distance),
+            f32::exp(-absorption.z() * distance),
+        );
+    }
+
+    // Enters or leaves the surface's interior when the scattered ray goes through it
+    pub fn update(&mut self, hit: &HitRecord, scattered: &Ray) {
+        let Some(absorption) = hit.material.interior_absorption() else {
+            return;
+        };
+
+        // The normal faces the incoming ray, so reflections stay on its side
+        if scattered.direction().dot(hit.normal) >= 0.0 {
+            return;
+        }
+
+        if hit.front_face {
+            if self.depth < MAX_NESTED_MEDIA {
+                self.absorption[self.depth] = absorption;
+            }
+
+            self.depth += 1;
+        }
+
+        else {
+            self.depth = self.depth.saturating_sub(1);
+        }
+    }
+}
+
+// Homogeneous medium filling the whole scene
+pub struct Fog {
+    density: f32,
+    phase_function: Arc<HenyeyGreenstein>,
+}
+
+impl Fog {
+    pub fn new(density: f32, albedo: Vector3, anisotropy: f32) -> Self {
+        return Self { density, phase_function: Arc::new(HenyeyGreenstein::new(albedo, anisotropy)) };
+    }
+
+    // Distance to the next scattering event
+    pub fn sample_distance(&self) -> f32 {
+        return -f32::ln(1.0 - rng::random_f32()) / self.density;
+    }
+
+    // Chance of getting that far without scattering
+    pub fn transmittance(&self, distance: f32) -> f32 {
+        return f32::exp(-self.density * distance);
+    }
+
+    // Scattering event along the ray, with the phase function as its material
+    pub fn event(&self, ray: &Ray, distance: f32) -> HitRecord {
+        let mut hit_record = HitRecord::new(self.phase_function.clone());
+        hit_record.t = distance;
+        hit_record.point = ray.at(distance);
+
+        return hit_record;
+    }
+
+    fn scatter(&self, ray: &Ray, distance: f32) -> (Vector3, Ray) {
+        let hit_record = self.event(ray, distance);
+
+        let mut attenuation = Vector3::default();
+        let mut scattered = Ray::default();
+        self.phase_function.scatter(ray, &hit_record, &mut attenuation, &mut scattered);
+
+        return (attenuation, scattered);
+    }
+}
+
+// Unidirectional path tracer, iterative so depth doesn't eat the wasm stack
+pub struct PathIntegrator {
+    max_depth: u32,
+    fog: Option<Fog>,
+}
+
+impl PathIntegrator {
+    pub fn new(max_depth: u32, fog: Option<Fog>) -> Self {
+        return Self { max_depth, fog };
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        let mut media = MediumStack::default();
+        // Colour of the wavelength once the path is spectral, applied to what it adds to the film
+        let mut film = Vector3::new(1.0, 1.0, 1.0);
+
+        for bounce in 0..self.max_depth {
+            // Russian roulette, dim paths are likely to die but the survivors make up for them
+            if bounce >= ROULETTE_MIN_BOUNCES {
+                let survival = survival_probability(throughput);
+
+                if rng::random_f32() >= survival {
+                    break;
+                }
+
+                throughput = throughput / survival;
+            }
+
+            let hit = world.hit(&ray, Interval::new(0.001, f32::INFINITY));
+
+            // Fog can scatter the ray before it reaches anything, including rays headed for the sky
+            if let Some(fog) = &self.fog {
+                let fog_distance = fog.sample_distance();
+
+                if fog_distance < hit.as_ref().map_or(f32::INFINITY, |hit| hit.t) {
+                    let (attenuation, mut scattered) = fog.scatter(&ray, fog_distance);
+                    scattered.set_wavelength(ray.wavelength());
+
+                    throughput = throughput.component_mul(attenuation).component_mul(media.transmittance(fog_distance));
+                    ray = scattered;
+                    continue;
+                }
+            }
+
+            if let Some(hit) = hit {
+                let mut scattered: Ray = Ray::default();
+                let mut attenuation = Vector3::default();
+                throughput = throughput.component_mul(media.transmittance(hit.t));
+
+                if hit.material.is_emissive() {
+                    radiance += film.component_mul(throughput).component_mul(hit.material.emitted(&hit));
+                }
+
+                if hit.material.scatter(&ray, &hit, &mut attenuation, &mut scattered) {
+                    if let Some(weight) = carry_wavelength(&ray, &mut scattered) {
+                        film = weight;
+                    }
+
+                    throughput = throughput.component_mul(attenuation);
+                    media.update(&hit, &scattered);
+                    ray = scattered;
+
+                    // Lost to the surface, nothing left to carry
+                    if throughput == Vector3::new(0.0, 0.0, 0.0) {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                if hit.material.is_emissive() {
+                    break;
+                }
+            }
+
+            // Missed everything, or was absorbed by something other than a light, which has always shown the sky
+            radiance += film.component_mul(throughput).component_mul(background(&ray));
+            break;
+        }
+
+        return radiance;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Aov {
+    Normal,
+    Depth,
+    Albedo,
+    Uv,
+    ObjectId,
+}
+
+// Shows one property of the first hit, for chasing geometry and material bugs
+pub struct AovIntegrator {
+    aov: Aov,
+    forward: Vector3,
+    depth_range: f32,
+}
+
+impl AovIntegrator {
+    pub fn new(aov: Aov, forward: Vector3, depth_range: f32) -> Self {
+        let forward = if forward.norm_squared() > 0.0 { forward.normalize() } else { Vector3::new(0.0, 0.0, -1.0) };
+        return Self { aov, forward, depth_range: depth_range.max(1e-4) };
+    }
+}
+
+impl Integrator for AovIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        let Some(hit) = world.hit(ray, Interval::new(0.001, f32::INFINITY)) else {
+            return Vector3::new(0.0, 0.0, 0.0);
+        };
+
+        let value = match self.aov {
+            Aov::Normal => 0.5 * (hit.normal + 1.0),
+            // Along the view direction rather than along the ray, so flat walls stay flat
+            Aov::Depth => {
+                let depth = ((hit.point - ray.origin()).dot(self.forward) / self.depth_range).clamp(0.0, 1.0);
+                Vector3::new(depth, depth, depth)
+            },
+            // Whatever the material attenuates by on its first bounce, close enough to albedo
+            Aov::Albedo => {
+                let mut attenuation = Vector3::default();
+                let mut scattered = Ray::default();
+                hit.material.scatter(ray, &hit, &mut attenuation, &mut scattered);
+                attenuation
+            },
+            Aov::Uv => Vector3::new(hit.u, hit.v, 0.0),
+            Aov::ObjectId => id_color(hit.object_id),
+        };
+
+        // Output gets gamma 2 applied, square so the screen shows the raw value
+        return value.component_mul(value);
+    }
+}
+
+// Hashes an ID to a bright colour, neighbouring IDs look nothing alike
+fn id_color(id: u32) -> Vector3 {
+    let mut hash = id.wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85EB_CA6B);
+    hash ^= hash >> 13;
+
+    let channel = |shift: u32| 0.25 + 0.75 * ((hash >> shift) & 0xFF) as f32 / 255.0;
+    return Vector3::new(channel(0), channel(8), channel(16));
+}
+
+// Fraction of the hemisphere that's open within a radius, cheap enough for previews while moving
+pub struct AmbientOcclusionIntegrator {
+    radius: f32,
+    samples: u32,
+}
+
+impl AmbientOcclusionIntegrator {
+    pub fn new(radius: f32, samples: u32) -> Self {
+        return Self { radius: if radius > 0.0 { radius } else { f32::INFINITY }, samples: samples.max(1) };
+    }
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        let Some(hit) = world.hit(ray, Interval::new(0.001, f32::INFINITY)) else {
+            return background(ray);
+        };
+
+        let mut open = 0;
+        for _ in 0..self.samples {
+            // Cosine weighted, the same way Lambertian scatters
+            let mut direction = hit.normal + vector_utils::random_vec3_unit();
+            if vector_utils::near_zero(direction) {
+                direction = hit.normal;
+            }
+
+            if world.hit(&Ray::new(hit.point, direction.normalize()), Interval::new(0.001, self.radius)).is_none() {
+                open += 1;
+            }
+        }
+
+        // Squared like the AOVs, so the screen shows the raw fraction
+        let visibility = open as f32 / self.samples as f32;
+        return Vector3::new(visibility * visibility, visibility * visibility, visibility * visibility);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{material::{Dielectric, Lambertian}, sphere::sphere::Sphere};
+
+    use super::*;
+
+    fn crossing(material: Arc<dyn Material>, front_face: bool) -> (HitRecord, Ray) {
+        let mut hit = HitRecord::new(material);
+        hit.normal = Vector3::new(0.0, 1.0, 0.0);
+        hit.front_face = front_face;
+
+        return (hit, Ray::new(Vector3::default(), Vector3::new(0.0, -1.0, 0.0)));
+    }
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        return (a - b).norm() < 1e-5;
+    }
+
+    #[test]
+    fn glass_colour_is_what_one_unit_lets_through() {
+        let color = Vector3::new(0.8, 0.3, 0.3);
+        let (hit, ray) = crossing(Arc::new(Dielectric::with_color(1.5, color)), true);
+
+        let mut media = MediumStack::default();
+        media.update(&hit, &ray);
+        assert!(close(media.transmittance(1.0), color));
+        assert!(close(media.transmittance(2.0), color.component_mul(color)));
+    }
+
+    #[test]
+    fn nested_media_absorb_by_the_innermost() {
+        let red = Vector3::new(0.8, 0.3, 0.3);
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::with_color(1.5, red));
+        let bubble: Arc<dyn Material> = Arc::new(Dielectric::new(1.0 / 1.5));
+        let mut media = MediumStack::default();
+
+        let (hit, ray) = crossing(glass.clone(), true);
+        media.update(&hit, &ray);
+        let (hit, ray) = crossing(bubble.clone(), true);
+        media.update(&hit, &ray);
+        assert!(close(media.transmittance(1.0), Vector3::new(1.0, 1.0, 1.0)));
+
+        // Out of the bubble, back in the glass
+        let (hit, ray) = crossing(bubble, false);
+        media.update(&hit, &ray);
+        assert!(close(media.transmittance(1.0), red));
+
+        let (hit, ray) = crossing(glass, false);
+        media.update(&hit, &ray);
+        assert!(close(media.transmittance(1.0), Vector3::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn reflections_stay_outside() {
+        let (hit, _) = crossing(Arc::new(Dielectric::with_color(1.5, Vector3::new(0.5, 0.5, 0.5))), true);
+        let reflected = Ray::new(Vector3::default(), Vector3::new(0.0, 1.0, 0.0));
+
+        let mut media = MediumStack::default();
+        media.update(&hit, &reflected);
+        assert!(close(media.transmittance(1.0), Vector3::new(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn fog_free_paths_average_one_over_density() {
+        let fog = Fog::new(0.5, Vector3::new(0.9, 0.9, 0.9), 0.0);
+        let samples = 20000;
+        let mean = (0..samples).map(|_| fog.sample_distance()).sum::<f32>() / samples as f32;
+
+        assert!((mean - 2.0).abs() < 0.1, "mean free path {}", mean);
+    }
+
+    #[test]
+    fn fog_transmittance_is_the_chance_of_no_event() {
+        let fog = Fog::new(0.5, Vector3::new(0.9, 0.9, 0.9), 0.0);
+        let samples = 20000;
+        let through = (0..samples).filter(|_| fog.sample_distance() > 3.0).count() as f32 / samples as f32;
+
+        assert!((through - fog.transmittance(3.0)).abs() < 0.02);
+        assert!((fog.transmittance(0.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fog_scatters_where_it_was_sampled() {
+        let albedo = Vector3::new(0.9, 0.8, 0.7);
+        let fog = Fog::new(0.5, albedo, 0.3);
+        let ray = Ray::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.0, 0.0, -1.0));
+        let (attenuation, scattered) = fog.scatter(&ray, 2.5);
+
+        assert!(close(attenuation, albedo));
+        assert!(close(scattered.origin(), Vector3::new(1.0, 2.0, 0.5)));
+        assert!((scattered.direction().norm() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn roulette_only_plays_with_dim_paths() {
+        assert_eq!(survival_probability(Vector3::new(1.5, 0.2, 0.1)), 1.0);
+        assert_eq!(survival_probability(Vector3::new(0.0, 0.0, 0.0)), ROULETTE_MIN_SURVIVAL);
+        assert!((survival_probability(Vector3::new(0.1, 0.4, 0.2)) - 0.4).abs() < 1e-6);
+    }
+
+    // Lets rays straight through, dimming them
+    struct Veil {
+        transmission: f32,
+    }
+
+    impl Material for Veil {
+        fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+            *scattered_ray = Ray::new(hit_record.point, incoming_ray.direction());
+            *attenuation = Vector3::new(self.transmission, self.transmission, self.transmission);
+
+            return true;
+        }
+    }
+
+    #[test]
+    fn roulette_keeps_the_expected_radiance() {
+        let transmission = 0.8;
+        let veils = 10;
+        let mut world = ObjectList::default();
+        for i in 0..veils {
+            world.add(Arc::new(Sphere::new(Vector3::default(), 1.0 + i as f32, Arc::new(Veil { transmission }))));
+        }
+
+        // Without roulette every path goes through every veil and then sees the sky, which is 0.5 in red at the horizon
+        let expected = transmission.powi(veils as i32) * 0.5;
+
+        let integrator = PathIntegrator::new(veils + 1, None);
+        let ray = Ray::new(Vector3::default(), Vector3::new(0.0, 0.0, -1.0));
+        let samples = 50000;
+        let mean = (0..samples).map(|_| integrator.ray_color(&ray, &world).x()).sum::<f32>() / samples as f32;
+
+        assert!((mean - expected).abs() < 0.05 * expected, "roulette mean {} but {} without it", mean, expected);
+    }
+
+    #[test]
+    fn ambient_occlusion_matches_a_sphere_interior() {
+        let mut world = ObjectList::default();
+        world.add(Arc::new(Sphere::new(Vector3::default(), 1.0, Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))))));
+
+        // From the wall of a unit sphere the far wall is 2 cos theta away, so with cosine weighted
+        // rays a radius of 1 finds it a quarter of the time
+        let ao = AmbientOcclusionIntegrator::new(1.0, 64);
+        let ray = Ray::new(Vector3::default(), Vector3::new(0.0, 0.0, -1.0));
+        let samples = 200;
+        let open = (0..samples).map(|_| ao.ray_color(&ray, &world).x().sqrt()).sum::<f32>() / samples as f32;
+
+        assert!((open - 0.75).abs() < 0.02, "open {}", open);
+    }
+
+    #[test]
+    fn cosine_directions_are_unit_and_above_the_surface() {
+        let normal = Vector3::new(0.0, 0.6, 0.8);
+        for _ in 0..1000 {
+            let direction = cosine_direction(normal);
+            assert!((Vector3::new(direction.x(), direction.y(), direction.z()).norm() - 1.0).abs() < 1e-5);
+            assert!(direction.dot(normal) >= 0.0);
+        }
+    }
+}
@@ -10,17 +10,35 @@ mod material;
 pub mod vector3;
 mod rng;
 mod shared_mem;
+mod texture;
+mod perlin;
+mod microfacet;
+mod spectrum;
+mod constant_medium;
+mod heterogeneous_medium;
+mod integrator;
+mod bdpt;
+mod photon_map;
+mod mlt;
+mod sampler;
+mod blue_noise;
 
 use std::cell::RefCell;
 use std::sync::{Arc, OnceLock, RwLock};
 
 use camera::Camera;
+use constant_medium::constant_medium::ConstantMedium;
+use heterogeneous_medium::heterogeneous_medium::{DensityField, GridDensity, HeterogeneousMedium, NoiseDensity, VolumeParams};
 use object_list::object_list::ObjectList;
+use scene_object::scene_object::SceneObject;
 use shared_mem::SharedMem;
 use sphere::sphere::Sphere;
 use wasm_bindgen::prelude::*;
 use crate::vector3::Vector3;
+use crate::material::{MappedMaterialParams, Material, Principled, PrincipledParams};
 use crate::rng::Xorshift32State;
+use crate::spectrum::IorModel;
+use crate::texture::{CheckerParams, CheckerTexture, ImageTexture, NoiseKind, NoiseTexture, ScaledTexture, SolidColor, Texture};
 
 #[wasm_bindgen]
 extern "C" {
@@ -46,12 +64,18 @@ thread_local! {
 
 static WORLD: OnceLock<Arc<RwLock<ObjectList>>> = OnceLock::new();
 static SETTINGS: OnceLock<RwLock<SharedMem>> = OnceLock::new();
+// Textures are referenced from JS by their index
+static TEXTURES: OnceLock<RwLock<Vec<Arc<dyn Texture>>>> = OnceLock::new();
+// Same for materials
+static MATERIALS: OnceLock<RwLock<Vec<Arc<dyn Material>>>> = OnceLock::new();
 
 #[wasm_bindgen(start)]
 fn init() {
     // Scene
     let world = ObjectList::default();
     let _ = WORLD.set(Arc::new(RwLock::new(world)));
+    let _ = TEXTURES.set(RwLock::new(Vec::new()));
+    let _ = MATERIALS.set(RwLock::new(Vec::new()));
 }
 
 #[wasm_bindgen]
@@ -59,6 +83,9 @@ pub async fn init_settings() -> *const SharedMem {
     let settings = SharedMem::default();
     let _ = SETTINGS.set(RwLock::new(settings));
 
+    // Made now so the first preview doesn't wait for it
+    blue_noise::generate_mask();
+
     return SETTINGS.get().unwrap().write().as_deref().unwrap();
 }
 
@@ -76,29 +103,261 @@ pub async fn trace() {
 
 #[wasm_bindgen]
 pub fn add_sphere(x: f32, y: f32, z: f32, diameter: f32, material: u32, r: f32, g: f32, b: f32, special: f32) {
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, basic_material(material, r, g, b, special))));
+}
+
+// Same materials as add_sphere, but returns a handle so they can be layered or mixed
+#[wasm_bindgen]
+pub fn add_basic_material(material: u32, r: f32, g: f32, b: f32, special: f32) -> u32 {
+    return add_material(basic_material(material, r, g, b, special));
+}
+
+fn basic_material(material: u32, r: f32, g: f32, b: f32, special: f32) -> Arc<dyn Material> {
+    // Metal 
+    if material == 1 {
+        return Arc::new(material::Metal::new(Vector3::new(r, g, b), special));
+    }
+    // Dielectric
+    else if material == 2 {
+        return Arc::new(material::Dielectric::with_color(special, Vector3::new(r, g, b)));
+    }
+    // GGX conductor, special is roughness
+    else if material == 3 {
+        return Arc::new(material::Conductor::from_reflectance(Vector3::new(r, g, b), special));
+    }
+
+    // Default to Lambertian
+    return Arc::new(material::Lambertian::new(Vector3::new(r, g, b)));
+}
+
+// Returns a handle for add_textured_sphere
+#[wasm_bindgen]
+pub fn add_image_texture(width: u32, height: u32, data: &[u8]) -> u32 {
+    return add_texture(Arc::new(ImageTexture::from_rgba(width, height, data)));
+}
+
+#[wasm_bindgen]
+pub fn add_checker_texture(params: &CheckerParams) -> u32 {
+    return add_texture(Arc::new(CheckerTexture::from_params(params)));
+}
+
+// 0 = Perlin noise, 1 = turbulence, 2 = marble
+#[wasm_bindgen]
+pub fn add_noise_texture(kind: u32, scale: f32, r: f32, g: f32, b: f32, seed: u32) -> u32 {
+    return add_texture(Arc::new(NoiseTexture::new(NoiseKind::from_u32(kind), scale, Vector3::new(r, g, b), seed)));
+}
+
+// Normal and height maps, not gamma decoded
+#[wasm_bindgen]
+pub fn add_data_texture(width: u32, height: u32, data: &[u8]) -> u32 {
+    return add_texture(Arc::new(ImageTexture::from_rgba_data(width, height, data)));
+}
+
+fn add_texture(texture: Arc<dyn Texture>) -> u32 {
+    let mut textures = TEXTURES.get().unwrap().write().unwrap();
+    textures.push(texture);
+
+    return (textures.len() - 1) as u32;
+}
+
+fn get_texture_handle(handle: u32) -> Option<Arc<dyn Texture>> {
+    return TEXTURES.get()?.read().ok()?.get(handle as usize).cloned();
+}
+
+#[wasm_bindgen]
+pub fn add_textured_sphere(x: f32, y: f32, z: f32, diameter: f32, material: u32, texture: u32, special: f32) {
+    let Some(texture) = get_texture_handle(texture) else {
+        console_log!("No texture with handle {}", texture);
+        return;
+    };
+
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, textured_material(material, texture, special))));
+}
+
+// 0 = gold, 1 = copper, 2 = aluminium
+#[wasm_bindgen]
+pub fn add_conductor_sphere(x: f32, y: f32, z: f32, diameter: f32, preset: u32, roughness: f32) {
+    let mat = Arc::new(material::Conductor::from_preset(material::ConductorPreset::from_u32(preset), roughness));
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat)));
+}
+
+#[wasm_bindgen]
+pub fn add_rough_dielectric_sphere(x: f32, y: f32, z: f32, diameter: f32, refraction_index: f32, roughness: f32) {
+    let mat = Arc::new(material::RoughDielectric::new(refraction_index, roughness));
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat)));
+}
+
+// Smoke or fog filling a sphere, density is per unit distance
+#[wasm_bindgen]
+pub fn add_constant_medium_sphere(x: f32, y: f32, z: f32, diameter: f32, density: f32, r: f32, g: f32, b: f32) {
+    let phase_function = Arc::new(material::Isotropic::new(Vector3::new(r, g, b)));
+    let boundary = Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, phase_function.clone()));
+    add_object(Arc::new(ConstantMedium::new(boundary, density, phase_function)));
+}
+
+// Density grid from JS, x varies fastest, then y, then z
+#[wasm_bindgen]
+pub fn add_grid_volume(params: &VolumeParams, nx: u32, ny: u32, nz: u32, data: &[f32]) {
+    let (min, max) = volume_bounds(params);
+    add_volume(params, Arc::new(GridDensity::new(nx, ny, nz, min, max, data, params.density)));
+}
+
+// Perlin turbulence clouds, scale is the noise frequency
+#[wasm_bindgen]
+pub fn add_noise_volume(params: &VolumeParams, scale: f32, seed: u32) {
+    add_volume(params, Arc::new(NoiseDensity::new(scale, params.density, seed)));
+}
+
+fn volume_bounds(params: &VolumeParams) -> (Vector3, Vector3) {
+    return (Vector3::new(params.min_x, params.min_y, params.min_z), Vector3::new(params.max_x, params.max_y, params.max_z));
+}
+
+fn add_volume(params: &VolumeParams, density: Arc<dyn DensityField>) {
+    let (min, max) = volume_bounds(params);
+    let albedo = Vector3::new(params.albedo_r, params.albedo_g, params.albedo_b);
+    let phase_function = Arc::new(material::HenyeyGreenstein::new(albedo, params.anisotropy));
+
+    add_object(Arc::new(HeterogeneousMedium::new(min, max, density, phase_function)));
+}
+
+#[wasm_bindgen]
+pub fn add_mapped_sphere(x: f32, y: f32, z: f32, diameter: f32, params: &MappedMaterialParams) {
+    let (Some(texture), Some(map)) = (get_texture_handle(params.texture), get_texture_handle(params.map)) else {
+        console_log!("No texture with handle {} or {}", params.texture, params.map);
+        return;
+    };
+
+    let inner = textured_material(params.material, texture, params.special);
+    let mat: Arc<dyn Material> = if params.map_kind == 1 {
+        Arc::new(material::BumpMap::new(inner, map, params.strength))
+    }
+
+    else {
+        Arc::new(material::NormalMap::new(inner, map, params.strength))
+    };
+
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat)));
+}
+
+// Returns a handle for add_sphere_with_material
+// This is the default for imported assets, glTF materials map straight onto the params
+#[wasm_bindgen]
+pub fn add_principled_material(params: &PrincipledParams) -> u32 {
+    let color = Vector3::new(params.base_r, params.base_g, params.base_b);
+    let texture = params.base_color_texture.and_then(get_texture_handle);
+
+    if texture.is_none() && params.base_color_texture.is_some() {
+        console_log!("No texture with handle {}, using the base colour", params.base_color_texture.unwrap());
+    }
+
+    let base_color: Arc<dyn Texture> = match texture {
+        Some(texture) => Arc::new(ScaledTexture::new(texture, color)),
+        None => Arc::new(SolidColor::new(color)),
+    };
+
+    return add_material(Arc::new(Principled::new(params, base_color)));
+}
+
+#[wasm_bindgen]
+pub fn add_sphere_with_material(x: f32, y: f32, z: f32, diameter: f32, material: u32) {
+    let Some(mat) = get_material_handle(material) else {
+        console_log!("No material with handle {}", material);
+        return;
+    };
+
+    add_object(Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat)));
+}
+
+// 0 = Cauchy with a and b (b in square micrometres), 1 = BK7 crown glass, 2 = dense flint
+// A Cauchy index below 1 falls back to plain glass
+#[wasm_bindgen]
+pub fn add_dispersive_material(model: u32, a: f32, b: f32) -> u32 {
+    let ior = match model {
+        1 => IorModel::BK7,
+        2 => IorModel::DENSE_FLINT,
+        _ => IorModel::cauchy(a, b).unwrap_or_else(|| {
+            console_log!("Cauchy a = {} and b = {} give an index below 1, using 1.5", a, b);
+            IorModel::Constant(1.5)
+        }),
+    };
+
+    return add_material(Arc::new(material::Dielectric::dispersive(ior)));
+}
+
+// Thickness is in nanometres, substrate_ior is 1.0 for a soap bubble
+// base is a material handle to put the film over, like water under an oil slick
+#[wasm_bindgen]
+pub fn add_thin_film_material(thickness: f32, film_ior: f32, substrate_ior: f32, base: Option<u32>) -> u32 {
+    let base = base.and_then(get_material_handle);
+    return add_material(Arc::new(material::ThinFilm::new(thickness, film_ior, substrate_ior, base)));
+}
+
+// Albedo and mean free path are per channel, a longer red path gives skin its glow
+#[wasm_bindgen]
+pub fn add_subsurface_material(r: f32, g: f32, b: f32, mfp_r: f32, mfp_g: f32, mfp_b: f32, refraction_index: f32) -> u32 {
+    return add_material(Arc::new(material::Subsurface::new(Vector3::new(r, g, b), Vector3::new(mfp_r, mfp_g, mfp_b), refraction_index)));
+}
+
+// Mask is a texture handle, without one weight picks between the two
+// Returns undefined if either material doesn't exist
+#[wasm_bindgen]
+pub fn add_mix_material(first: u32, second: u32, weight: f32, mask: Option<u32>) -> Option<u32> {
+    let (Some(first), Some(second)) = (get_material_handle(first), get_material_handle(second)) else {
+        console_log!("No material with handle {} or {}", first, second);
+        return None;
+    };
+
+    let mix = match mask.and_then(get_texture_handle) {
+        Some(mask) => material::Mix::new(first, second, mask),
+        None => material::Mix::with_weight(first, second, weight),
+    };
+
+    return Some(add_material(Arc::new(mix)));
+}
+
+// Clear coat over an existing material, r g b tints the coat
+// Returns undefined if the base doesn't exist
+#[wasm_bindgen]
+pub fn add_coated_material(base: u32, refraction_index: f32, roughness: f32, r: f32, g: f32, b: f32) -> Option<u32> {
+    let Some(base) = get_material_handle(base) else {
+        console_log!("No material with handle {}", base);
+        return None;
+    };
+
+    return Some(add_material(Arc::new(material::Coated::new(base, refraction_index, roughness, Vector3::new(r, g, b)))));
+}
+
+// Area light, use it with add_sphere_with_material
+#[wasm_bindgen]
+pub fn add_diffuse_light(r: f32, g: f32, b: f32) -> u32 {
+    return add_material(Arc::new(material::DiffuseLight::new(Vector3::new(r, g, b))));
+}
+
+fn add_material(material: Arc<dyn Material>) -> u32 {
+    let mut materials = MATERIALS.get().unwrap().write().unwrap();
+    materials.push(material);
+
+    return (materials.len() - 1) as u32;
+}
+
+fn get_material_handle(handle: u32) -> Option<Arc<dyn Material>> {
+    return MATERIALS.get()?.read().ok()?.get(handle as usize).cloned();
+}
+
+fn textured_material(material: u32, texture: Arc<dyn Texture>, special: f32) -> Arc<dyn Material> {
+    // Metal
+    if material == 1 {
+        return Arc::new(material::Metal::from_texture(texture, special));
+    }
+
+    // Default to Lambertian, dielectrics don't take a texture
+    return Arc::new(material::Lambertian::from_texture(texture));
+}
+
+fn add_object(object: Arc<dyn SceneObject>) {
     if let Some(world) = WORLD.get() {
         match world.write() {
-            Ok(mut world) => {
-                // Metal 
-                if material == 1 {
-                    let mat = Arc::new(material::Metal::new(Vector3::new(r, g, b), special));
-                    let object = Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat));
-                    world.add(object);
-                }
-                // Dielectric
-                else if material == 2 {
-                    let mat = Arc::new(material::Dielectric::new(special));
-                    let object = Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat));
-                    world.add(object);
-                }
-                // Default to Lambertian
-                else {
-                    let mat = Arc::new(material::Lambertian::new(Vector3::new(r, g, b)));
-                    let object = Arc::new(Sphere::new(Vector3::new(x, y, z), diameter, mat));
-                    world.add(object);
-                } 
-            },
-
+            Ok(mut world) => world.add(object),
             Err(_) => console_log!("Failed to get write lock on world")
         }
     }
@@ -1,17 +1,51 @@
 // I would prefer a BSDF but this *is* simpler as it is in the book
-use crate::{ray::ray::Ray, rng, scene_object::scene_object::HitRecord, vector_utils::{self, near_zero, random_vec3_sphere, random_vec3_unit, reflect, refract}, vector3::Vector3};
+use core::f32;
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{microfacet::{fresnel_conductor_rgb, fresnel_schlick, TrowbridgeReitz}, ray::ray::Ray, rng, scene_object::scene_object::HitRecord, spectrum::{self, IorModel}, texture::{SolidColor, Texture}, vector_utils::{self, near_zero, random_vec3_sphere, random_vec3_unit, reflect, refract}, vector3::Vector3};
 
 pub trait Material: Sync + Send {
     fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool;
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Vector3 {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    fn is_emissive(&self) -> bool {
+        return false;
+    }
+
+    // BSDF times the cosine at the hit, and the pdf scatter picks wi with
+    // Both directions point away from the surface, wo back along the incoming ray
+    // None for anything scatter can't be evaluated for, integrators treat those as specular
+    fn evaluate(&self, _hit_record: &HitRecord, _wo: Vector3, _wi: Vector3) -> Option<(Vector3, f32)> {
+        return None;
+    }
+
+    // Absorption coefficient inside a closed surface rays go through, like glass
+    // Integrators keep track of which one a path is in and apply it to every segment
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return None;
+    }
+
+    // Volumes scatter from a point rather than off a surface, so no cosine goes into their densities
+    fn is_phase_function(&self) -> bool {
+        return false;
+    }
 }
 
-#[derive(Default)]
 pub struct Lambertian {
-    albedo: Vector3
+    albedo: Arc<dyn Texture>
 }
 
 impl Lambertian {
     pub fn new(albedo: Vector3) -> Self {
+        return Self { albedo: Arc::new(SolidColor::new(albedo)) };
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>) -> Self {
         return Self { albedo };
     }
 }
@@ -25,20 +59,34 @@ impl Material for Lambertian {
         }
 
         *scattered_ray = Ray::new(hit_record.point, scatter_dicretion.normalize());
-        *attenuation = self.albedo;
+        *attenuation = self.albedo.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
 
         return true;
     }
+
+    // Scatter only reflects into the side the normal faces
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        let cos_theta = hit_record.normal.dot(wi);
+        if cos_theta <= 0.0 || hit_record.normal.dot(wo) <= 0.0 {
+            return Some((Vector3::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        let albedo = self.albedo.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
+        return Some(((cos_theta / f32::consts::PI) * albedo, cos_theta / f32::consts::PI));
+    }
 }
 
-#[derive(Default)]
 pub struct Metal {
-    albedo: Vector3,
+    albedo: Arc<dyn Texture>,
     fuzz: f32
 }
 
 impl Metal {
     pub fn new(albedo: Vector3, fuzz: f32) -> Self {
+        return Self::from_texture(Arc::new(SolidColor::new(albedo)), fuzz);
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>, fuzz: f32) -> Self {
         // equivalent of fuzz < 1 ? fuzz : 1.0
         return Self { albedo, fuzz: if fuzz < 1.0 { fuzz } else { 1.0 } };
     }
@@ -48,21 +96,198 @@ impl Material for Metal {
     fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
         let mut reflection_direction = vector_utils::reflect(incoming_ray.direction(), hit_record.normal);
         reflection_direction = reflection_direction.normalize() + (self.fuzz * random_vec3_sphere());
-        *scattered_ray = Ray::new(hit_record.point, reflection_direction.normalize());
-        *attenuation = self.albedo;
+        reflection_direction = reflection_direction.normalize();
+        // Fuzz is ignored for the differentials, it's close enough
+        let differentials = hit_record.reflect_differentials(incoming_ray, reflection_direction);
+        *scattered_ray = Ray::with_differentials(hit_record.point, reflection_direction, differentials);
+        *attenuation = self.albedo.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
 
         return scattered_ray.direction().dot(hit_record.normal) > 0.0;
     }
+
+    // Scatter picks a point in a ball of radius fuzz around the mirror direction, so the density of a
+    // direction is how much of the ball lies along it. Anything under the surface is absorbed
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        if self.fuzz <= 0.0 {
+            return None;
+        }
+
+        let none = Some((Vector3::new(0.0, 0.0, 0.0), 0.0));
+        if hit_record.normal.dot(wo) <= 0.0 || hit_record.normal.dot(wi) <= 0.0 {
+            return none;
+        }
+
+        // Where the ray along wi goes in and out of the ball
+        let cos_theta = wi.dot(reflect(-1.0 * wo, hit_record.normal).normalize());
+        let discriminant = self.fuzz * self.fuzz - (1.0 - cos_theta * cos_theta);
+        if discriminant < 0.0 || cos_theta <= 0.0 {
+            return none;
+        }
+
+        let t0 = (cos_theta - discriminant.sqrt()).max(0.0);
+        let t1 = cos_theta + discriminant.sqrt();
+        let pdf = (t1 * t1 * t1 - t0 * t0 * t0) / (4.0 * f32::consts::PI * self.fuzz * self.fuzz * self.fuzz);
+        let albedo = self.albedo.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
+
+        return Some((pdf * albedo, pdf));
+    }
+}
+
+// Single scattering loses whatever ends up under the surface, that's black rather than the sky
+// absorbing the path would show
+fn lost_energy(hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+    *attenuation = Vector3::new(0.0, 0.0, 0.0);
+    *scattered_ray = Ray::new(hit_record.point, hit_record.normal);
+
+    return true;
+}
+
+// Mirror bounces keep their differentials, rough ones spread too much to bother
+fn glossy_ray(incoming_ray: &Ray, hit_record: &HitRecord, distribution: &TrowbridgeReitz, direction: Vector3) -> Ray {
+    if distribution.effectively_smooth() {
+        return Ray::with_differentials(hit_record.point, direction, hit_record.reflect_differentials(incoming_ray, direction));
+    }
+
+    return Ray::new(hit_record.point, direction);
+}
+
+// Reflection from wo to wi in the local frame, for a direction sample_normal picked
+// f * cos without Fresnel, the pdf and the microfacet normal, f * cos / pdf is sample_weight
+fn glossy_lobe(distribution: &TrowbridgeReitz, wo: Vector3, wi: Vector3) -> (f32, f32, Vector3) {
+    let wm = wo + wi;
+    if wo.z() <= 0.0 || wi.z() <= 0.0 || near_zero(wm) {
+        return (0.0, 0.0, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    let wm = wm.normalize();
+    let pdf = distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm));
+
+    return (pdf * distribution.sample_weight(wo, wi), pdf, wm);
+}
+
+// Scatter sees the normal on the side the ray came from, evaluate can be asked from either side
+fn seen_from(hit_record: &HitRecord, wo: Vector3) -> HitRecord {
+    let mut seen = hit_record.clone();
+    if hit_record.normal.dot(wo) < 0.0 {
+        seen.normal = -1.0 * hit_record.normal;
+        seen.front_face = !hit_record.front_face;
+    }
+
+    return seen;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+impl ConductorPreset {
+    pub fn from_u32(preset: u32) -> Self {
+        return match preset {
+            1 => ConductorPreset::Copper,
+            2 => ConductorPreset::Aluminium,
+            _ => ConductorPreset::Gold,
+        };
+    }
+
+    // Complex IOR (eta, k) at roughly 650nm, 550nm and 450nm
+    fn eta_k(self) -> (Vector3, Vector3) {
+        return match self {
+            ConductorPreset::Gold => (Vector3::new(0.143, 0.374, 1.442), Vector3::new(3.983, 2.385, 1.603)),
+            ConductorPreset::Copper => (Vector3::new(0.200, 0.924, 1.102), Vector3::new(3.912, 2.452, 2.142)),
+            ConductorPreset::Aluminium => (Vector3::new(1.657, 0.880, 0.521), Vector3::new(9.224, 6.270, 4.837)),
+        };
+    }
+}
+
+// GGX conductor with visible normal sampling, replaces the fuzz hack in Metal
+pub struct Conductor {
+    eta: Vector3,
+    k: Vector3,
+    distribution: TrowbridgeReitz,
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3, k: Vector3, roughness: f32) -> Self {
+        return Self { eta, k, distribution: TrowbridgeReitz::from_roughness(roughness) };
+    }
+
+    pub fn from_preset(preset: ConductorPreset, roughness: f32) -> Self {
+        let (eta, k) = preset.eta_k();
+        return Self::new(eta, k, roughness);
+    }
+
+    // For picking a colour instead of an IOR, reflectance is at normal incidence
+    pub fn from_reflectance(reflectance: Vector3, roughness: f32) -> Self {
+        // Invert Fresnel at normal incidence with no absorption
+        let eta = |r: f32| {
+            let sqrt_r = f32::sqrt(r.clamp(0.0, 0.99));
+            (1.0 + sqrt_r) / (1.0 - sqrt_r)
+        };
+
+        let eta = Vector3::new(eta(reflectance.x()), eta(reflectance.y()), eta(reflectance.z()));
+        return Self::new(eta, Vector3::new(0.0, 0.0, 0.0), roughness);
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let frame = hit_record.tangent_frame();
+        let wo = frame.to_local(-1.0 * incoming_ray.direction());
+        if wo.z() <= 0.0 {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        let wm = self.distribution.sample_normal(wo);
+        let wi = reflect(-1.0 * wo, wm);
+
+        // Reflected under the surface
+        if wi.z() <= 0.0 {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        // f * cos / pdf with visible normals leaves F * G2 / G1
+        let fresnel = fresnel_conductor_rgb(wo.dot(wm), self.eta, self.k);
+        *attenuation = self.distribution.sample_weight(wo, wi) * fresnel;
+        *scattered_ray = glossy_ray(incoming_ray, hit_record, &self.distribution, frame.to_world(wi).normalize());
+
+        return true;
+    }
+
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        if self.distribution.effectively_smooth() {
+            return None;
+        }
+
+        let frame = hit_record.tangent_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let (f, pdf, wm) = glossy_lobe(&self.distribution, wo, wi);
+
+        return Some((f * fresnel_conductor_rgb(wo.dot(wm), self.eta, self.k), pdf));
+    }
 }
 
-#[derive(Default)]
 pub struct Dielectric {
-    refraction_index: f32
+    ior: IorModel,
+    // Beer-Lambert absorption coefficient per unit distance
+    absorption: Vector3,
 }
 
 impl Dielectric {
     pub fn new(refraction_index: f32) -> Self {
-        return Self { refraction_index };
+        return Self::dispersive(IorModel::Constant(refraction_index));
+    }
+
+    // Colour is what's left of white light after travelling one unit through the glass
+    pub fn with_color(refraction_index: f32, color: Vector3) -> Self {
+        let absorption = |c: f32| -f32::ln(c.clamp(1e-4, 1.0));
+        return Self { ior: IorModel::Constant(refraction_index), absorption: Vector3::new(absorption(color.x()), absorption(color.y()), absorption(color.z())) };
+    }
+
+    pub fn dispersive(ior: IorModel) -> Self {
+        return Self { ior, absorption: Vector3::new(0.0, 0.0, 0.0) };
     }
 
     fn reflectance(cosine: f32, refraction_index: f32) -> f32 {
@@ -75,10 +300,22 @@ impl Dielectric {
 
 impl Material for Dielectric {
     fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
-        // Transmission colour
+        // The colour comes from absorption along the way, the integrator applies it
         *attenuation = Vector3::new(1.0, 1.0, 1.0);
 
-        let ri = if hit_record.front_face {1.0/self.refraction_index} else {self.refraction_index};
+        // Dispersion needs a single wavelength, pick one if the path is still RGB
+        // The integrator weights the path by its colour from here on
+        let mut wavelength = incoming_ray.wavelength();
+        if self.ior.is_dispersive() && wavelength.is_none() {
+            wavelength = Some(spectrum::sample_wavelength(rng::random_f32()));
+        }
+
+        let refraction_index = match wavelength {
+            Some(lambda) => self.ior.at(lambda),
+            None => self.ior.nominal(),
+        };
+
+        let ri = if hit_record.front_face {1.0/refraction_index} else {refraction_index};
 
         let unit_direction = incoming_ray.direction();
         let cos_theta = f32::min(-unit_direction.dot(hit_record.normal), 1.0);
@@ -86,17 +323,1268 @@ impl Material for Dielectric {
 
         let cannot_refract = ri * sin_theta > 1.0;
         let direction;
+        let differentials;
 
         if cannot_refract || Self::reflectance(cos_theta, ri) > rng::random_f32() {
-            direction = reflect(unit_direction, hit_record.normal);
+            direction = reflect(unit_direction, hit_record.normal).normalize();
+            differentials = hit_record.reflect_differentials(incoming_ray, direction);
         }
 
         else {
-            direction = refract(unit_direction, hit_record.normal, ri);
+            direction = refract(unit_direction, hit_record.normal, ri).normalize();
+            differentials = hit_record.refract_differentials(incoming_ray, direction, ri);
         }
         
-        *scattered_ray = Ray::new(hit_record.point, direction.normalize());
+        *scattered_ray = Ray::with_differentials(hit_record.point, direction, differentials);
+        scattered_ray.set_wavelength(wavelength);
+
+        return true;
+    }
+
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return Some(self.absorption);
+    }
+}
+
+// Textured material with a normal or height map, for add_mapped_sphere
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct MappedMaterialParams {
+    // Same as add_textured_sphere, 1 = metal, anything else is Lambertian
+    pub material: u32,
+    pub texture: u32,
+    pub special: f32,
+    // 0 is a tangent space normal map, 1 is a height map
+    pub map_kind: u32,
+    pub map: u32,
+    // Scales the normal map tangents or the bump height
+    pub strength: f32,
+}
+
+#[wasm_bindgen]
+impl MappedMaterialParams {
+    #[wasm_bindgen(constructor)]
+    pub fn new(texture: u32, map: u32) -> Self {
+        return Self { material: 0, texture, special: 0.0, map_kind: 0, map, strength: 1.0 };
+    }
+}
+
+// Perturbs the normal with a tangent space normal map before handing the hit to the inner material
+// The map should be uploaded as data so it isn't gamma decoded
+pub struct NormalMap {
+    inner: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+    strength: f32,
+}
+
+impl NormalMap {
+    pub fn new(inner: Arc<dyn Material>, map: Arc<dyn Texture>, strength: f32) -> Self {
+        return Self { inner, map, strength };
+    }
+
+    fn perturb(&self, hit_record: &HitRecord) -> HitRecord {
+        let sample = self.map.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
+        // [0, 1] to [-1, 1], strength only scales the tangent part
+        let local = 2.0 * sample - Vector3::new(1.0, 1.0, 1.0);
+        let local = Vector3::new(self.strength * local.x(), self.strength * local.y(), local.z());
+
+        let mut perturbed = hit_record.clone();
+        if local.norm_squared() > 0.0 {
+            perturbed.normal = hit_record.tangent_frame().to_world(local).normalize();
+        }
+
+        return perturbed;
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        return self.inner.scatter(incoming_ray, &self.perturb(hit_record), attenuation, scattered_ray);
+    }
+
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        return self.inner.evaluate(&self.perturb(hit_record), wo, wi);
+    }
+
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return self.inner.interior_absorption();
+    }
+}
+
+// Perturbs the normal from the gradient of a height map, pbrt style
+pub struct BumpMap {
+    inner: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f32,
+}
+
+impl BumpMap {
+    pub fn new(inner: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f32) -> Self {
+        return Self { inner, height, scale };
+    }
+
+    fn height_at(&self, u: f32, v: f32, point: Vector3) -> f32 {
+        return self.scale * self.height.value(u, v, point).x();
+    }
+
+    fn perturb(&self, hit_record: &HitRecord) -> HitRecord {
+        // Step about half a pixel if we know the footprint
+        let (mut du, mut dv) = match &hit_record.differentials {
+            Some(sd) => (0.5 * (sd.dudx.abs() + sd.dudy.abs()), 0.5 * (sd.dvdx.abs() + sd.dvdy.abs())),
+            None => (0.0, 0.0),
+        };
+        if du == 0.0 { du = 0.0005; }
+        if dv == 0.0 { dv = 0.0005; }
+
+        let (u, v, point) = (hit_record.u, hit_record.v, hit_record.point);
+        let displace = self.height_at(u, v, point);
+        let displace_u = self.height_at(u + du, v, point + du * hit_record.dpdu);
+        let displace_v = self.height_at(u, v + dv, point + dv * hit_record.dpdv);
+
+        // Tangents of the displaced surface p + d n, the d dn/du term matters on small curved objects
+        let dpdu = hit_record.dpdu + ((displace_u - displace) / du) * hit_record.normal + displace * hit_record.dndu;
+        let dpdv = hit_record.dpdv + ((displace_v - displace) / dv) * hit_record.normal + displace * hit_record.dndv;
+
+        let mut perturbed = hit_record.clone();
+        let normal = dpdu.cross(dpdv);
+        if normal.norm_squared() > 0.0 {
+            let normal = normal.normalize();
+            // Keep it on the same side as the geometric normal
+            perturbed.normal = if normal.dot(hit_record.normal) < 0.0 { -1.0 * normal } else { normal };
+        }
+
+        return perturbed;
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        return self.inner.scatter(incoming_ray, &self.perturb(hit_record), attenuation, scattered_ray);
+    }
+
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        return self.inner.evaluate(&self.perturb(hit_record), wo, wi);
+    }
+
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return self.inner.interior_absorption();
+    }
+}
+
+// Frosted glass, microfacet BTDF with the same visible normal sampling as Conductor
+pub struct RoughDielectric {
+    refraction_index: f32,
+    distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f32, roughness: f32) -> Self {
+        return Self { refraction_index, distribution: TrowbridgeReitz::from_roughness(roughness) };
+    }
+
+    // Chance of reflecting off a microfacet, all of it past the critical angle
+    fn fresnel(cos_theta: f32, ri: f32) -> f32 {
+        let cos_theta = cos_theta.min(1.0);
+        let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+        if ri * sin_theta > 1.0 {
+            return 1.0;
+        }
+
+        return Dielectric::reflectance(cos_theta, ri);
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let ri = if hit_record.front_face {1.0/self.refraction_index} else {self.refraction_index};
+
+        // The normal always faces the incoming ray, so wo is in the upper hemisphere either way
+        let frame = hit_record.tangent_frame();
+        let wo = frame.to_local(-1.0 * incoming_ray.direction());
+        if wo.z() <= 0.0 {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        let wm = self.distribution.sample_normal(wo);
+
+        // Pick reflection or transmission by Fresnel, so F cancels out of the weight
+        let reflected = Self::fresnel(wo.dot(wm), ri) > rng::random_f32();
+        let wi = if reflected { reflect(-1.0 * wo, wm) } else { refract(-1.0 * wo, wm, ri) };
+
+        // Microfacet sent it to the wrong side of the macro surface
+        if (reflected && wi.z() <= 0.0) || (!reflected && wi.z() >= 0.0) {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        // Transmission colour, G2 / G1 is the visible normal sampling weight
+        let weight = self.distribution.sample_weight(wo, wi);
+        *attenuation = Vector3::new(weight, weight, weight);
+
+        let direction = frame.to_world(wi).normalize();
+        let differentials = if !self.distribution.effectively_smooth() {
+            None
+        }
+
+        else if reflected {
+            hit_record.reflect_differentials(incoming_ray, direction)
+        }
+
+        else {
+            hit_record.refract_differentials(incoming_ray, direction, ri)
+        };
+
+        *scattered_ray = Ray::with_differentials(hit_record.point, direction, differentials);
+
+        return true;
+    }
+
+    // Fresnel picks the side, so it goes into both pdfs
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        if self.distribution.effectively_smooth() {
+            return None;
+        }
+
+        let hit_record = seen_from(hit_record, wo);
+        let ri = if hit_record.front_face {1.0/self.refraction_index} else {self.refraction_index};
+        let frame = hit_record.tangent_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+
+        let none = Some((Vector3::new(0.0, 0.0, 0.0), 0.0));
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return none;
+        }
+
+        if wi.z() > 0.0 {
+            let (f, pdf, wm) = glossy_lobe(&self.distribution, wo, wi);
+            let fresnel = Self::fresnel(wo.dot(wm), ri);
+            return Some(((fresnel * f) * Vector3::new(1.0, 1.0, 1.0), fresnel * pdf));
+        }
+
+        // Generalised half vector, on the side of wo
+        let wm = ri * wo + wi;
+        if near_zero(wm) {
+            return none;
+        }
+
+        let wm = if wm.z() < 0.0 { -1.0 * wm.normalize() } else { wm.normalize() };
+        if wo.dot(wm) <= 0.0 || wi.dot(wm) >= 0.0 {
+            return none;
+        }
+
+        // Jacobian of refracting through wm
+        let denom = wi.dot(wm) + ri * wo.dot(wm);
+        let pdf = self.distribution.d_visible(wo, wm) * wi.dot(wm).abs() / (denom * denom) * (1.0 - Self::fresnel(wo.dot(wm), ri));
+        let f = pdf * self.distribution.sample_weight(wo, wi);
+
+        return Some((f * Vector3::new(1.0, 1.0, 1.0), pdf));
+    }
+
+    // Clear inside
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return Some(Vector3::new(0.0, 0.0, 0.0));
+    }
+}
+
+// Everything the principled material takes, laid out after glTF's metallic-roughness model
+// base_color, metallic and roughness are the core factors, specular, clearcoat, sheen,
+// transmission and ior come from the KHR_materials_* extensions
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct PrincipledParams {
+    pub base_r: f32,
+    pub base_g: f32,
+    pub base_b: f32,
+    // Texture handle, multiplies the base colour
+    pub base_color_texture: Option<u32>,
+    pub metallic: f32,
+    pub roughness: f32,
+    // 0.5 is the usual 4% reflectance
+    pub specular: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub sheen: f32,
+    // 0 is white sheen, 1 is tinted by the base colour
+    pub sheen_tint: f32,
+    pub transmission: f32,
+    pub ior: f32,
+}
+
+#[wasm_bindgen]
+impl PrincipledParams {
+    // glTF defaults
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        return Self {
+            base_r: 1.0,
+            base_g: 1.0,
+            base_b: 1.0,
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            specular: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+        };
+    }
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// One material for imported assets, picks a single lobe per bounce
+// Layering order is clearcoat, then metal, then glass, then specular over diffuse
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: f32,
+    specular: f32,
+    clearcoat: f32,
+    sheen: f32,
+    sheen_tint: f32,
+    transmission: f32,
+    distribution: TrowbridgeReitz,
+    clearcoat_distribution: TrowbridgeReitz,
+    glass: RoughDielectric,
+}
+
+impl Principled {
+    // base_color replaces the colour in params when textured
+    pub fn new(params: &PrincipledParams, base_color: Arc<dyn Texture>) -> Self {
+        return Self {
+            base_color,
+            metallic: params.metallic.clamp(0.0, 1.0),
+            specular: params.specular.max(0.0),
+            clearcoat: params.clearcoat.clamp(0.0, 1.0),
+            sheen: params.sheen.max(0.0),
+            sheen_tint: params.sheen_tint.clamp(0.0, 1.0),
+            transmission: params.transmission.clamp(0.0, 1.0),
+            distribution: TrowbridgeReitz::from_roughness(params.roughness),
+            clearcoat_distribution: TrowbridgeReitz::from_roughness(params.clearcoat_roughness),
+            glass: RoughDielectric::new(params.ior, params.roughness),
+        };
+    }
+
+    fn schlick_weight(cos_theta: f32) -> f32 {
+        return f32::powi(1.0 - cos_theta.clamp(0.0, 1.0), 5);
+    }
+
+    // Clear layer over everything, always 4% reflectance
+    fn clearcoat_fresnel(&self, cos_theta: f32) -> f32 {
+        return self.clearcoat * (0.04 + 0.96 * Self::schlick_weight(cos_theta));
+    }
+
+    // Dielectric specular, whatever isn't reflected goes to diffuse
+    fn specular_fresnel(&self, cos_theta: f32) -> f32 {
+        let specular_f0 = 0.08 * self.specular;
+        return specular_f0 + (1.0 - specular_f0) * Self::schlick_weight(cos_theta);
+    }
+
+    // Disney sheen over the diffuse base, the sheen takes its share from the base so grazing
+    // angles can't come out brighter than white. cos_theta is between the light and the half vector
+    fn diffuse_with_sheen(&self, base: Vector3, cos_theta: f32) -> Vector3 {
+        let sheen_color = (1.0 - self.sheen_tint) * Vector3::new(1.0, 1.0, 1.0) + self.sheen_tint * base;
+        let weight = (f32::consts::PI * self.sheen * Self::schlick_weight(cos_theta)).min(1.0);
+
+        return (1.0 - weight) * base + weight * sheen_color;
+    }
+
+    // Glossy bounce off the given distribution, None if it went under the surface
+    fn sample_glossy(distribution: &TrowbridgeReitz, wo: Vector3) -> Option<(Vector3, Vector3)> {
+        let wm = distribution.sample_normal(wo);
+        let wi = reflect(-1.0 * wo, wm);
+
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        return Some((wi, wm));
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let frame = hit_record.tangent_frame();
+        let wo = frame.to_local(-1.0 * incoming_ray.direction());
+        if wo.z() <= 0.0 {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        let base = self.base_color.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
+
+        if rng::random_f32() < self.clearcoat_fresnel(wo.z()) {
+            let Some((wi, _)) = Self::sample_glossy(&self.clearcoat_distribution, wo) else { return lost_energy(hit_record, attenuation, scattered_ray); };
+
+            let weight = self.clearcoat_distribution.sample_weight(wo, wi);
+            *attenuation = Vector3::new(weight, weight, weight);
+            *scattered_ray = glossy_ray(incoming_ray, hit_record, &self.clearcoat_distribution, frame.to_world(wi).normalize());
+            return true;
+        }
+
+        // Metal, the base colour is F0
+        if rng::random_f32() < self.metallic {
+            let Some((wi, wm)) = Self::sample_glossy(&self.distribution, wo) else { return lost_energy(hit_record, attenuation, scattered_ray); };
+
+            *attenuation = self.distribution.sample_weight(wo, wi) * fresnel_schlick(wo.dot(wm), base);
+            *scattered_ray = glossy_ray(incoming_ray, hit_record, &self.distribution, frame.to_world(wi).normalize());
+            return true;
+        }
+
+        // Glass tinted by the base colour, once for the way in so a ray through it isn't tinted twice
+        if rng::random_f32() < self.transmission {
+            let scattered = self.glass.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+            if hit_record.front_face && scattered_ray.direction().dot(hit_record.normal) < 0.0 {
+                *attenuation = attenuation.component_mul(base);
+            }
+
+            return scattered;
+        }
+
+        if rng::random_f32() < self.specular_fresnel(wo.z()) {
+            let Some((wi, _)) = Self::sample_glossy(&self.distribution, wo) else { return lost_energy(hit_record, attenuation, scattered_ray); };
+
+            let weight = self.distribution.sample_weight(wo, wi);
+            *attenuation = Vector3::new(weight, weight, weight);
+            *scattered_ray = glossy_ray(incoming_ray, hit_record, &self.distribution, frame.to_world(wi).normalize());
+            return true;
+        }
+
+        let mut scatter_direction = hit_record.normal + random_vec3_unit();
+        if near_zero(scatter_direction) {
+            scatter_direction = hit_record.normal;
+        }
+        let scatter_direction = scatter_direction.normalize();
+
+        // Sheen is grazing retro-reflection for cloth
+        let half = (scatter_direction - incoming_ray.direction()).normalize();
+        *attenuation = self.diffuse_with_sheen(base, scatter_direction.dot(half));
+        *scattered_ray = Ray::new(hit_record.point, scatter_direction);
 
         return true;
     }
-}
\ No newline at end of file
+
+    // Every lobe with the odds scatter picks it with, specular if one that can be picked is smooth
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        let hit_record = &seen_from(hit_record, wo);
+        let frame = hit_record.tangent_frame();
+        let (wo_local, wi_local) = (frame.to_local(wo), frame.to_local(wi));
+
+        let clearcoat = self.clearcoat_fresnel(wo_local.z());
+        let metal = (1.0 - clearcoat) * self.metallic;
+        let glass = (1.0 - clearcoat) * (1.0 - self.metallic) * self.transmission;
+        let dielectric = (1.0 - clearcoat) * (1.0 - self.metallic) * (1.0 - self.transmission);
+        let specular = dielectric * self.specular_fresnel(wo_local.z());
+        let diffuse = dielectric - specular;
+
+        if (clearcoat > 0.0 && self.clearcoat_distribution.effectively_smooth()) || (metal + glass + specular > 0.0 && self.distribution.effectively_smooth()) {
+            return None;
+        }
+
+        if wo_local.z() <= 0.0 {
+            return Some((Vector3::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        let base = self.base_color.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref());
+        let white = Vector3::new(1.0, 1.0, 1.0);
+
+        let mut f = Vector3::new(0.0, 0.0, 0.0);
+        let mut pdf = 0.0;
+
+        // Lobes that can't be picked are skipped, their distribution may be smooth and give NAN
+        if clearcoat > 0.0 {
+            let (clearcoat_f, clearcoat_pdf, _) = glossy_lobe(&self.clearcoat_distribution, wo_local, wi_local);
+            f += (clearcoat * clearcoat_f) * white;
+            pdf += clearcoat * clearcoat_pdf;
+        }
+
+        if metal + specular > 0.0 {
+            let (glossy_f, glossy_pdf, wm) = glossy_lobe(&self.distribution, wo_local, wi_local);
+            f += (specular * glossy_f) * white + (metal * glossy_f) * fresnel_schlick(wo_local.dot(wm), base);
+            pdf += (metal + specular) * glossy_pdf;
+        }
+
+        if glass > 0.0 {
+            let (glass_f, glass_pdf) = self.glass.evaluate(hit_record, wo, wi)?;
+            let tint = if hit_record.front_face && wi_local.z() < 0.0 { base } else { white };
+            f += glass * glass_f.component_mul(tint);
+            pdf += glass * glass_pdf;
+        }
+
+        if wi_local.z() > 0.0 {
+            let half = (wo_local + wi_local).normalize();
+            let cosine_pdf = wi_local.z() / f32::consts::PI;
+            f += (diffuse * cosine_pdf) * self.diffuse_with_sheen(base, wi_local.dot(half));
+            pdf += diffuse * cosine_pdf;
+        }
+
+        return Some((f, pdf));
+    }
+
+    fn interior_absorption(&self) -> Option<Vector3> {
+        if self.transmission > 0.0 {
+            return self.glass.interior_absorption();
+        }
+
+        return None;
+    }
+}
+
+// Picks one of two materials per hit, mask is 0 for the first and 1 for the second
+pub struct Mix {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    mask: Arc<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, mask: Arc<dyn Texture>) -> Self {
+        return Self { first, second, mask };
+    }
+
+    pub fn with_weight(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f32) -> Self {
+        return Self::new(first, second, Arc::new(SolidColor::new(Vector3::new(weight, weight, weight))));
+    }
+}
+
+impl Mix {
+    fn weight(&self, hit_record: &HitRecord) -> f32 {
+        return self.mask.value_filtered(hit_record.u, hit_record.v, hit_record.point, hit_record.differentials.as_ref()).x();
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        if rng::random_f32() < self.weight(hit_record) {
+            return self.second.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+        }
+
+        return self.first.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vector3 {
+        let weight = self.weight(hit_record);
+        return (1.0 - weight) * self.first.emitted(hit_record) + weight * self.second.emitted(hit_record);
+    }
+
+    fn is_emissive(&self) -> bool {
+        return self.first.is_emissive() || self.second.is_emissive();
+    }
+
+    // Scatter picks one by the weight, so both the BSDF and the pdf are the weighted sums
+    // Specular if either one is
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        let weight = self.weight(hit_record);
+        let (first, first_pdf) = self.first.evaluate(hit_record, wo, wi)?;
+        let (second, second_pdf) = self.second.evaluate(hit_record, wo, wi)?;
+
+        return Some(((1.0 - weight) * first + weight * second, (1.0 - weight) * first_pdf + weight * second_pdf));
+    }
+
+    // Can't pick per hit, so the first one with an interior wins
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return self.first.interior_absorption().or(self.second.interior_absorption());
+    }
+
+    // Only a volume when both halves are, a surface half still needs its cosine
+    fn is_phase_function(&self) -> bool {
+        return self.first.is_phase_function() && self.second.is_phase_function();
+    }
+}
+
+// Clear dielectric layer over any base, like varnish or car paint
+// The layer is thin, so refraction through it is ignored and only its Fresnel and tint matter
+pub struct Coated {
+    base: Arc<dyn Material>,
+    refraction_index: f32,
+    // Applied to light going through the coat, counts for both ways
+    tint: Vector3,
+    distribution: TrowbridgeReitz,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, refraction_index: f32, roughness: f32, tint: Vector3) -> Self {
+        return Self { base, refraction_index, tint, distribution: TrowbridgeReitz::from_roughness(roughness) };
+    }
+
+    // Chance of bouncing off the coat, taken at the macro normal so it only depends on wo
+    fn fresnel(&self, hit_record: &HitRecord, cos_theta: f32) -> f32 {
+        let ri = if hit_record.front_face {1.0/self.refraction_index} else {self.refraction_index};
+        return Dielectric::reflectance(cos_theta.min(1.0), ri);
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let frame = hit_record.tangent_frame();
+        let wo = frame.to_local(-1.0 * incoming_ray.direction());
+        if wo.z() <= 0.0 {
+            return lost_energy(hit_record, attenuation, scattered_ray);
+        }
+
+        if self.fresnel(hit_record, wo.z()) > rng::random_f32() {
+            let wm = self.distribution.sample_normal(wo);
+            let wi = reflect(-1.0 * wo, wm);
+            if wi.z() <= 0.0 {
+                return lost_energy(hit_record, attenuation, scattered_ray);
+            }
+
+            let weight = self.distribution.sample_weight(wo, wi);
+            *attenuation = Vector3::new(weight, weight, weight);
+            *scattered_ray = glossy_ray(incoming_ray, hit_record, &self.distribution, frame.to_world(wi).normalize());
+            return true;
+        }
+
+        let scattered = self.base.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+        *attenuation = attenuation.component_mul(self.tint.component_mul(self.tint));
+
+        return scattered;
+    }
+
+    fn evaluate(&self, hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        let (base_f, base_pdf) = self.base.evaluate(hit_record, wo, wi)?;
+        if self.distribution.effectively_smooth() {
+            return None;
+        }
+
+        let seen = seen_from(hit_record, wo);
+        let frame = seen.tangent_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z() <= 0.0 {
+            return Some((Vector3::new(0.0, 0.0, 0.0), 0.0));
+        }
+
+        let fresnel = self.fresnel(&seen, wo.z());
+        let (coat_f, coat_pdf, _) = glossy_lobe(&self.distribution, wo, wi);
+        let f = (fresnel * coat_f) * Vector3::new(1.0, 1.0, 1.0) + (1.0 - fresnel) * base_f.component_mul(self.tint.component_mul(self.tint));
+
+        return Some((f, fresnel * coat_pdf + (1.0 - fresnel) * base_pdf));
+    }
+
+    fn interior_absorption(&self) -> Option<Vector3> {
+        return self.base.interior_absorption();
+    }
+}
+
+// Thin film interference, soap bubbles with no base and oil slicks over a base
+// Reflectance per wavelength comes from the two film interfaces and the phase between them
+pub struct ThinFilm {
+    // In nanometres
+    thickness: f32,
+    film_ior: f32,
+    // What's under the film, 1.0 for a bubble
+    substrate_ior: f32,
+    // None lets the light straight through like a bubble does
+    base: Option<Arc<dyn Material>>,
+}
+
+impl ThinFilm {
+    // Wavelengths for the RGB estimate
+    const SPECTRAL_SAMPLES: u32 = 16;
+
+    pub fn new(thickness: f32, film_ior: f32, substrate_ior: f32, base: Option<Arc<dyn Material>>) -> Self {
+        return Self { thickness: thickness.max(0.0), film_ior, substrate_ior, base };
+    }
+
+    // Airy reflectance of the film from air, amplitudes come from Dielectric::reflectance
+    fn reflectance_at(&self, cos_theta: f32, lambda: f32) -> f32 {
+        let sin2_theta = 1.0 - cos_theta * cos_theta;
+        let sin2_film = sin2_theta / (self.film_ior * self.film_ior);
+        if sin2_film >= 1.0 {
+            return 1.0;
+        }
+        let cos_film = f32::sqrt(1.0 - sin2_film);
+
+        // Reflecting off a denser medium flips the phase
+        let amplitude = |reflectance: f32, denser: bool| if denser { -reflectance.sqrt() } else { reflectance.sqrt() };
+        let r12 = amplitude(Dielectric::reflectance(cos_theta, self.film_ior), self.film_ior > 1.0);
+        let r23 = amplitude(Dielectric::reflectance(cos_film, self.substrate_ior / self.film_ior), self.substrate_ior > self.film_ior);
+
+        let phase = 4.0 * f32::consts::PI * self.film_ior * self.thickness * cos_film / lambda;
+        let interference = 2.0 * r12 * r23 * f32::cos(phase);
+
+        return ((r12 * r12 + r23 * r23 + interference) / (1.0 + r12 * r12 * r23 * r23 + interference)).clamp(0.0, 1.0);
+    }
+
+    // RGB paths average over the visible range, spectral ones use their own wavelength
+    fn reflectance(&self, cos_theta: f32, wavelength: Option<f32>) -> Vector3 {
+        if let Some(lambda) = wavelength {
+            let r = self.reflectance_at(cos_theta, lambda);
+            return Vector3::new(r, r, r);
+        }
+
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+        for i in 0..Self::SPECTRAL_SAMPLES {
+            let lambda = spectrum::sample_wavelength((i as f32 + 0.5) / Self::SPECTRAL_SAMPLES as f32);
+            sum += self.reflectance_at(cos_theta, lambda) * spectrum::wavelength_to_rgb(lambda);
+        }
+
+        let rgb = sum / Self::SPECTRAL_SAMPLES as f32;
+        return Vector3::new(rgb.x().clamp(0.0, 1.0), rgb.y().clamp(0.0, 1.0), rgb.z().clamp(0.0, 1.0));
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let cos_theta = f32::min(-incoming_ray.direction().dot(hit_record.normal), 1.0);
+        let reflectance = self.reflectance(cos_theta, incoming_ray.wavelength());
+
+        // Choose by average reflectance and reweight, so the colour comes through in the attenuation
+        let probability = ((reflectance.x() + reflectance.y() + reflectance.z()) / 3.0).clamp(0.01, 0.99);
+
+        if rng::random_f32() < probability {
+            let direction = reflect(incoming_ray.direction(), hit_record.normal).normalize();
+            let differentials = hit_record.reflect_differentials(incoming_ray, direction);
+
+            *attenuation = reflectance / probability;
+            *scattered_ray = Ray::with_differentials(hit_record.point, direction, differentials);
+            return true;
+        }
+
+        let transmitted = (Vector3::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability);
+
+        match &self.base {
+            Some(base) => {
+                let scattered = base.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+                *attenuation = attenuation.component_mul(transmitted);
+                return scattered;
+            }
+
+            // Thin enough that the ray carries on as if nothing happened
+            None => {
+                *attenuation = transmitted;
+                *scattered_ray = Ray::with_differentials(hit_record.point, incoming_ray.direction(), incoming_ray.differentials());
+                return true;
+            }
+        }
+    }
+}
+
+// Phase function for constant media, scatters the same in every direction
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>
+}
+
+impl Isotropic {
+    pub fn new(albedo: Vector3) -> Self {
+        return Self { albedo: Arc::new(SolidColor::new(albedo)) };
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        *scattered_ray = Ray::new(hit_record.point, random_vec3_unit());
+        *attenuation = self.albedo.value(hit_record.u, hit_record.v, hit_record.point);
+
+        return true;
+    }
+
+    fn evaluate(&self, hit_record: &HitRecord, _wo: Vector3, _wi: Vector3) -> Option<(Vector3, f32)> {
+        let pdf = 1.0 / (4.0 * f32::consts::PI);
+        return Some((pdf * self.albedo.value(hit_record.u, hit_record.v, hit_record.point), pdf));
+    }
+
+    fn is_phase_function(&self) -> bool {
+        return true;
+    }
+}
+
+// Henyey-Greenstein phase function, g of 0 is the same as Isotropic
+pub struct HenyeyGreenstein {
+    albedo: Vector3,
+    g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Vector3, g: f32) -> Self {
+        // g of exactly 1 or -1 is a delta
+        return Self { albedo, g: g.clamp(-0.99, 0.99) };
+    }
+
+    // Density over the sphere of directions, cos_theta is between the old and new direction
+    pub fn phase(&self, cos_theta: f32) -> f32 {
+        let denom = 1.0 + self.g * self.g - 2.0 * self.g * cos_theta;
+        return (1.0 - self.g * self.g) / (4.0 * f32::consts::PI * denom * denom.sqrt());
+    }
+
+    // Cosine between the old and new direction
+    fn sample_cos_theta(&self, u: f32) -> f32 {
+        if self.g.abs() < 1e-3 {
+            return 1.0 - 2.0 * u;
+        }
+
+        let square = (1.0 - self.g * self.g) / (1.0 - self.g + 2.0 * self.g * u);
+        return ((1.0 + self.g * self.g - square * square) / (2.0 * self.g)).clamp(-1.0, 1.0);
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        let cos_theta = self.sample_cos_theta(rng::random_f32());
+        let sin_theta = f32::sqrt(f32::max(0.0, 1.0 - cos_theta * cos_theta));
+        let phi = 2.0 * f32::consts::PI * rng::random_f32();
+
+        // Around the direction of travel
+        let frame = vector_utils::Onb::new(incoming_ray.direction());
+        let local = Vector3::new(sin_theta * f32::cos(phi), sin_theta * f32::sin(phi), cos_theta);
+
+        // Sampled exactly, so only the albedo is left
+        *scattered_ray = Ray::new(hit_record.point, frame.to_world(local).normalize());
+        *attenuation = self.albedo;
+
+        return true;
+    }
+
+    // wo points back along the ray, so the angle to the new direction is against -wo
+    fn evaluate(&self, _hit_record: &HitRecord, wo: Vector3, wi: Vector3) -> Option<(Vector3, f32)> {
+        let pdf = self.phase(-wo.dot(wi));
+        return Some((pdf * self.albedo, pdf));
+    }
+
+    fn is_phase_function(&self) -> bool {
+        return true;
+    }
+}
+
+// Random walk subsurface scattering for skin, wax and marble
+// The boundary is plain Dielectric refraction. Inside, each back face hit tells us how far the
+// ray got, so a free flight distance shorter than that is a scattering event along the way.
+// Every step of the walk is a bounce, so it wants a higher max depth than the default
+pub struct Subsurface {
+    // Single scattering albedo
+    albedo: Vector3,
+    // One over the mean free path per channel
+    sigma_t: Vector3,
+    boundary: Dielectric,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Vector3, mean_free_path: Vector3, refraction_index: f32) -> Self {
+        let sigma_t = |mfp: f32| 1.0 / mfp.max(1e-4);
+
+        return Self {
+            albedo,
+            sigma_t: Vector3::new(sigma_t(mean_free_path.x()), sigma_t(mean_free_path.y()), sigma_t(mean_free_path.z())),
+            boundary: Dielectric::new(refraction_index),
+        };
+    }
+
+    fn transmittance(&self, distance: f32) -> Vector3 {
+        return Vector3::new(
+            f32::exp(-self.sigma_t.x() * distance),
+            f32::exp(-self.sigma_t.y() * distance),
+            f32::exp(-self.sigma_t.z() * distance),
+        );
+    }
+
+    fn average(vector: Vector3) -> f32 {
+        return (vector.x() + vector.y() + vector.z()) / 3.0;
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, incoming_ray: &Ray, hit_record: &HitRecord, attenuation: &mut Vector3, scattered_ray: &mut Ray) -> bool {
+        // Outside, it's just the boundary
+        if hit_record.front_face {
+            return self.boundary.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+        }
+
+        // Chromatic free flight, sample one channel and weight by the average pdf of all three
+        let channel = ((rng::random_f32() * 3.0) as usize).min(2);
+        let sigma = [self.sigma_t.x(), self.sigma_t.y(), self.sigma_t.z()][channel];
+        let distance = -f32::ln(1.0 - rng::random_f32()) / sigma;
+
+        if distance < hit_record.t {
+            let transmittance = self.transmittance(distance);
+            let density = self.sigma_t.component_mul(transmittance);
+
+            // sigma_s * T / pdf, sigma_s being albedo * sigma_t
+            *attenuation = self.albedo.component_mul(density) / Self::average(density);
+            *scattered_ray = Ray::new(incoming_ray.at(distance), random_vec3_unit());
+            scattered_ray.set_wavelength(incoming_ray.wavelength());
+            return true;
+        }
+
+        // Made it to the boundary
+        let transmittance = self.transmittance(hit_record.t);
+        let weight = transmittance / Self::average(transmittance);
+
+        let scattered = self.boundary.scatter(incoming_ray, hit_record, attenuation, scattered_ray);
+        *attenuation = attenuation.component_mul(weight);
+
+        return scattered;
+    }
+}
+
+// Area light, only the front face glows and nothing scatters off it
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vector3) -> Self {
+        return Self::from_texture(Arc::new(SolidColor::new(emit)));
+    }
+
+    pub fn from_texture(emit: Arc<dyn Texture>) -> Self {
+        return Self { emit };
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _incoming_ray: &Ray, _hit_record: &HitRecord, _attenuation: &mut Vector3, _scattered_ray: &mut Ray) -> bool {
+        return false;
+    }
+
+    fn emitted(&self, hit_record: &HitRecord) -> Vector3 {
+        if !hit_record.front_face {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+
+        return self.emit.value(hit_record.u, hit_record.v, hit_record.point);
+    }
+
+    fn is_emissive(&self) -> bool {
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat surface at the origin facing up
+    fn hit_facing_up(material: Arc<dyn Material>, front_face: bool) -> HitRecord {
+        let mut hit = HitRecord::new(material);
+        hit.normal = Vector3::new(0.0, 1.0, 0.0);
+        hit.dpdu = Vector3::new(1.0, 0.0, 0.0);
+        hit.dpdv = Vector3::new(0.0, 0.0, 1.0);
+        hit.front_face = front_face;
+        hit.t = 1.0;
+
+        return hit;
+    }
+
+    #[test]
+    fn schlick_reflectance_at_the_ends() {
+        let r0 = (0.5_f32 / 2.5) * (0.5 / 2.5);
+        assert!((Dielectric::reflectance(1.0, 1.5) - r0).abs() < 1e-6);
+        assert!((Dielectric::reflectance(0.0, 1.5) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_rough_dielectric_follows_snell() {
+        let material: Arc<dyn Material> = Arc::new(RoughDielectric::new(1.5, 0.0));
+        let hit = hit_facing_up(material.clone(), true);
+        let incoming = Ray::new(Vector3::new(-0.6, 0.8, 0.0), Vector3::new(0.6, -0.8, 0.0));
+
+        for _ in 0..32 {
+            let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+            assert!(material.scatter(&incoming, &hit, &mut attenuation, &mut scattered));
+
+            let direction = scattered.direction();
+            if direction.y() > 0.0 {
+                assert!((direction - Vector3::new(0.6, 0.8, 0.0)).norm() < 1e-4);
+            }
+
+            else {
+                assert!((direction.x() - 0.6 / 1.5).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_never_adds_energy() {
+        let material: Arc<dyn Material> = Arc::new(RoughDielectric::new(1.5, 0.6));
+        let incoming = Ray::new(Vector3::new(-0.8, 0.6, 0.0), Vector3::new(0.8, -0.6, 0.0));
+
+        for front_face in [true, false] {
+            let hit = hit_facing_up(material.clone(), front_face);
+            for _ in 0..256 {
+                let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+                assert!(material.scatter(&incoming, &hit, &mut attenuation, &mut scattered));
+                assert!(attenuation.x() <= 1.0 + 1e-5 && attenuation.x() >= 0.0);
+            }
+        }
+    }
+
+    fn principled(params: PrincipledParams, base: Vector3) -> Principled {
+        return Principled::new(&params, Arc::new(SolidColor::new(base)));
+    }
+
+    #[test]
+    fn sheen_takes_its_energy_from_the_base() {
+        let base = Vector3::new(0.9, 0.2, 0.1);
+        let params = PrincipledParams { metallic: 0.0, sheen: 1.0, ..PrincipledParams::default() };
+        let material = principled(params, base);
+
+        // Head on there's no sheen, at grazing angles it's all sheen
+        assert!((material.diffuse_with_sheen(base, 1.0) - base).norm() < 1e-6);
+        assert!((material.diffuse_with_sheen(base, 0.0) - Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-6);
+
+        for i in 0..=10 {
+            let color = material.diffuse_with_sheen(base, i as f32 / 10.0);
+            assert!(color.x() <= 1.0 && color.y() <= 1.0 && color.z() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn glass_is_tinted_once_per_transmission() {
+        let base = Vector3::new(0.9, 0.2, 0.1);
+        let params = PrincipledParams { metallic: 0.0, roughness: 0.0, transmission: 1.0, ..PrincipledParams::default() };
+        let material: Arc<dyn Material> = Arc::new(principled(params, base));
+        let incoming = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+
+        for front_face in [true, false] {
+            let hit = hit_facing_up(material.clone(), front_face);
+            for _ in 0..64 {
+                let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+                assert!(material.scatter(&incoming, &hit, &mut attenuation, &mut scattered));
+
+                let entering = front_face && scattered.direction().y() < 0.0;
+                let expected = if entering { base } else { Vector3::new(1.0, 1.0, 1.0) };
+                assert!((attenuation - expected).norm() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn mix_weights_emission_and_evaluation() {
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Vector3::new(4.0, 4.0, 4.0)));
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::new(1.0, 1.0, 1.0)));
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::new(1.0, 0.0, 0.0)));
+
+        let glowing = Mix::with_weight(white.clone(), light, 0.25);
+        let hit = hit_facing_up(Arc::new(Lambertian::new(Vector3::default())), true);
+        assert!(glowing.is_emissive());
+        assert!((glowing.emitted(&hit) - Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-6);
+
+        let mix = Mix::with_weight(white, red, 0.25);
+        assert!(!mix.is_emissive());
+
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        let (f, pdf) = mix.evaluate(&hit, up, up).unwrap();
+        let expected = (1.0 / f32::consts::PI) * Vector3::new(1.0, 0.75, 0.75);
+        assert!((f - expected).norm() < 1e-5);
+        assert!((pdf - 1.0 / f32::consts::PI).abs() < 1e-5);
+
+        // Anything specular makes the mix specular
+        let glass = Mix::with_weight(Arc::new(Dielectric::new(1.5)), Arc::new(Lambertian::new(Vector3::new(1.0, 1.0, 1.0))), 0.5);
+        assert!(glass.evaluate(&hit, up, up).is_none());
+    }
+
+    #[test]
+    fn mix_keeps_the_glass_absorption() {
+        let red = Dielectric::with_color(1.5, Vector3::new(0.8, 0.3, 0.3));
+        let absorption = red.interior_absorption().unwrap();
+        let red: Arc<dyn Material> = Arc::new(red);
+        let blue: Arc<dyn Material> = Arc::new(Dielectric::with_color(1.5, Vector3::new(0.3, 0.3, 0.8)));
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::new(1.0, 1.0, 1.0)));
+
+        assert!((Mix::with_weight(red.clone(), blue, 0.5).interior_absorption().unwrap() - absorption).norm() < 1e-6);
+        assert!((Mix::with_weight(white.clone(), red, 0.5).interior_absorption().unwrap() - absorption).norm() < 1e-6);
+        assert!(Mix::with_weight(white.clone(), white, 0.5).interior_absorption().is_none());
+    }
+
+    #[test]
+    fn mix_of_volumes_is_a_volume() {
+        let smoke: Arc<dyn Material> = Arc::new(Isotropic::new(Vector3::new(0.5, 0.5, 0.5)));
+        let cloud: Arc<dyn Material> = Arc::new(HenyeyGreenstein::new(Vector3::new(0.9, 0.9, 0.9), 0.6));
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new(Vector3::new(1.0, 1.0, 1.0)));
+
+        assert!(Mix::with_weight(smoke.clone(), cloud, 0.5).is_phase_function());
+        assert!(!Mix::with_weight(smoke, white, 0.5).is_phase_function());
+    }
+
+    // Something to look at every material from, 45 degrees off the normal
+    fn incoming_at_45() -> Ray {
+        return Ray::new(Vector3::new(-0.6, 0.8, 0.0), Vector3::new(0.6, -0.8, 0.0));
+    }
+
+    #[test]
+    fn evaluate_agrees_with_scatter() {
+        let materials: Vec<(Arc<dyn Material>, bool)> = vec![
+            (Arc::new(Metal::new(Vector3::new(0.9, 0.6, 0.3), 0.5)), true),
+            (Arc::new(Conductor::from_preset(ConductorPreset::Gold, 0.6)), true),
+            (Arc::new(RoughDielectric::new(1.5, 0.6)), true),
+            (Arc::new(RoughDielectric::new(1.5, 0.6)), false),
+            (Arc::new(HenyeyGreenstein::new(Vector3::new(0.8, 0.7, 0.6), 0.5)), true),
+        ];
+        let incoming = incoming_at_45();
+        let wo = -1.0 * incoming.direction();
+
+        for (material, front_face) in materials {
+            let hit = hit_facing_up(material.clone(), front_face);
+            for _ in 0..256 {
+                let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+                if !material.scatter(&incoming, &hit, &mut attenuation, &mut scattered) || attenuation == Vector3::default() {
+                    continue;
+                }
+
+                // Sampled by the pdf, so f * cos / pdf is the attenuation
+                let (f, pdf) = material.evaluate(&hit, wo, scattered.direction()).unwrap();
+                assert!(pdf > 0.0);
+                assert!((f / pdf - attenuation).norm() < 1e-3 * attenuation.norm().max(1.0));
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_surfaces_are_specular() {
+        let hit = hit_facing_up(Arc::new(Lambertian::new(Vector3::default())), true);
+        let (wo, wi) = (Vector3::new(-0.6, 0.8, 0.0), Vector3::new(0.6, 0.8, 0.0));
+
+        assert!(Metal::new(Vector3::new(1.0, 1.0, 1.0), 0.0).evaluate(&hit, wo, wi).is_none());
+        assert!(Conductor::from_preset(ConductorPreset::Copper, 0.0).evaluate(&hit, wo, wi).is_none());
+        assert!(RoughDielectric::new(1.5, 0.0).evaluate(&hit, wo, wi).is_none());
+        assert!(principled(PrincipledParams { roughness: 0.0, ..PrincipledParams::default() }, Vector3::new(1.0, 1.0, 1.0)).evaluate(&hit, wo, wi).is_none());
+    }
+
+    #[test]
+    fn principled_defaults_evaluate_to_finite_values() {
+        // No clearcoat, so its zero roughness distribution must not be touched
+        let material = principled(PrincipledParams { roughness: 0.5, ..PrincipledParams::new() }, Vector3::new(0.8, 0.5, 0.2));
+        let hit = hit_facing_up(Arc::new(Lambertian::new(Vector3::default())), true);
+
+        let (f, pdf) = material.evaluate(&hit, Vector3::new(-0.6, 0.8, 0.0), Vector3::new(0.6, 0.8, 0.0)).unwrap();
+        assert!(f.x().is_finite() && f.y().is_finite() && f.z().is_finite());
+        assert!(pdf.is_finite() && pdf > 0.0);
+    }
+
+    // Midpoint rule over the whole sphere of wi
+    fn integrate_pdf(material: &dyn Material, hit: &HitRecord, wo: Vector3) -> f32 {
+        let steps = 400;
+        let mut total = 0.0;
+        for i in 0..steps {
+            let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / steps as f32;
+            let sin_theta = f32::sqrt(1.0 - cos_theta * cos_theta);
+
+            let mut row = 0.0;
+            for j in 0..steps {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / steps as f32;
+                let wi = Vector3::new(sin_theta * f32::cos(phi), cos_theta, sin_theta * f32::sin(phi));
+                row += material.evaluate(hit, wo, wi).unwrap().1;
+            }
+
+            total += row;
+        }
+
+        return total * 4.0 * f32::consts::PI / (steps * steps) as f32;
+    }
+
+    #[test]
+    fn pdf_covers_what_scatter_keeps() {
+        let params = PrincipledParams { metallic: 0.3, roughness: 0.6, clearcoat: 0.5, clearcoat_roughness: 0.5, sheen: 0.5, transmission: 0.4, ..PrincipledParams::default() };
+        let materials: Vec<(Arc<dyn Material>, bool)> = vec![
+            (Arc::new(Metal::new(Vector3::new(1.0, 1.0, 1.0), 0.8)), true),
+            (Arc::new(Conductor::from_preset(ConductorPreset::Aluminium, 0.6)), true),
+            (Arc::new(RoughDielectric::new(1.5, 0.6)), true),
+            (Arc::new(RoughDielectric::new(1.5, 0.6)), false),
+            (Arc::new(principled(params, Vector3::new(0.8, 0.5, 0.2))), true),
+            (Arc::new(Coated::new(Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))), 1.5, 0.5, Vector3::new(0.9, 0.9, 0.9))), true),
+            (Arc::new(Isotropic::new(Vector3::new(0.5, 0.5, 0.5))), true),
+            (Arc::new(HenyeyGreenstein::new(Vector3::new(1.0, 1.0, 1.0), -0.4)), true),
+        ];
+        let incoming = incoming_at_45();
+
+        for (material, front_face) in materials {
+            let hit = hit_facing_up(material.clone(), front_face);
+
+            // Scatter loses energy under the surface, the pdf should be missing the same amount
+            let samples = 20000;
+            let kept = (0..samples).filter(|_| {
+                let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+                material.scatter(&incoming, &hit, &mut attenuation, &mut scattered) && attenuation != Vector3::default()
+            }).count() as f32 / samples as f32;
+
+            let total = integrate_pdf(material.as_ref(), &hit, -1.0 * incoming.direction());
+            assert!((total - kept).abs() < 0.02, "pdf integrates to {} but scatter keeps {}", total, kept);
+        }
+    }
+
+    #[test]
+    fn film_matching_the_outside_is_one_interface() {
+        let film = ThinFilm::new(300.0, 1.0, 1.5, None);
+        assert!((film.reflectance_at(1.0, 550.0) - Dielectric::reflectance(1.0, 1.5)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn quarter_wave_film_cancels_reflection() {
+        let film_ior = f32::sqrt(1.5);
+        let film = ThinFilm::new(550.0 / (4.0 * film_ior), film_ior, 1.5, None);
+
+        assert!(film.reflectance_at(1.0, 550.0) < 1e-4);
+        assert!(film.reflectance_at(1.0, 550.0) < Dielectric::reflectance(1.0, 1.5));
+    }
+
+    #[test]
+    fn film_reflectance_stays_in_range() {
+        let film = ThinFilm::new(420.0, 1.33, 1.0, None);
+        for i in 0..=20 {
+            let cos_theta = i as f32 / 20.0;
+            for lambda in [400.0, 480.0, 550.0, 620.0, 700.0] {
+                let r = film.reflectance_at(cos_theta, lambda);
+                assert!((0.0..=1.0).contains(&r));
+            }
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_is_normalised() {
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let phase = HenyeyGreenstein::new(Vector3::new(1.0, 1.0, 1.0), g);
+
+            // Only depends on the angle, so a midpoint rule in cos theta is enough
+            let steps = 20000;
+            let total: f32 = (0..steps).map(|i| phase.phase(-1.0 + 2.0 * (i as f32 + 0.5) / steps as f32)).sum();
+            let total = total * 2.0 / steps as f32 * 2.0 * f32::consts::PI;
+            assert!((total - 1.0).abs() < 1e-3, "g {} gave {}", g, total);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_samples_have_mean_cosine_g() {
+        for g in [-0.5, 0.0, 0.6] {
+            let phase = HenyeyGreenstein::new(Vector3::new(1.0, 1.0, 1.0), g);
+            let steps = 10000;
+            let mean: f32 = (0..steps).map(|i| phase.sample_cos_theta((i as f32 + 0.5) / steps as f32)).sum::<f32>() / steps as f32;
+            assert!((mean - g).abs() < 1e-3, "g {} gave {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn isotropic_scatters_in_unit_directions() {
+        let smoke: Arc<dyn Material> = Arc::new(Isotropic::new(Vector3::new(0.5, 0.5, 0.5)));
+        let hit = hit_facing_up(smoke.clone(), true);
+
+        // The boundary hit after a scatter assumes a unit direction
+        for _ in 0..1000 {
+            let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+            assert!(smoke.scatter(&incoming_at_45(), &hit, &mut attenuation, &mut scattered));
+
+            let direction = scattered.direction();
+            assert!((Vector3::new(direction.x(), direction.y(), direction.z()).norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn subsurface_walks_in_unit_directions() {
+        let skin: Arc<dyn Material> = Arc::new(Subsurface::new(Vector3::new(0.8, 0.5, 0.4), Vector3::new(0.1, 0.1, 0.1), 1.4));
+
+        // Inside with the boundary far away, so every step scatters in the medium
+        let mut hit = hit_facing_up(skin.clone(), false);
+        hit.t = f32::INFINITY;
+
+        for _ in 0..1000 {
+            let (mut attenuation, mut scattered) = (Vector3::default(), Ray::default());
+            assert!(skin.scatter(&incoming_at_45(), &hit, &mut attenuation, &mut scattered));
+
+            let direction = scattered.direction();
+            assert!((Vector3::new(direction.x(), direction.y(), direction.z()).norm() - 1.0).abs() < 1e-5);
+        }
+    }
+}
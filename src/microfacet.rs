@@ -0,0 +1,243 @@
+// Trowbridge-Reitz (GGX) microfacet distribution, mostly following pbrt
+// Everything here works in the local shading frame where z is the normal
+use core::f32;
+
+use crate::{rng, vector3::Vector3};
+
+// Below this the surface is treated as a perfect mirror
+const SMOOTH_ALPHA: f32 = 1e-3;
+
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        return Self { alpha_x, alpha_y };
+    }
+
+    // Squared roughness feels more linear to artists
+    pub fn from_roughness(roughness: f32) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0) * roughness.clamp(0.0, 1.0);
+        return Self::new(alpha, alpha);
+    }
+
+    pub fn effectively_smooth(&self) -> bool {
+        return self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA;
+    }
+
+    // Distribution of normals
+    pub fn d(&self, wm: Vector3) -> f32 {
+        let cos2_theta = wm.z() * wm.z();
+        if cos2_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let e = (wm.x() * wm.x()) / (self.alpha_x * self.alpha_x) + (wm.y() * wm.y()) / (self.alpha_y * self.alpha_y);
+        let denom = cos2_theta + e;
+
+        return 1.0 / (f32::consts::PI * self.alpha_x * self.alpha_y * denom * denom);
+    }
+
+    // Smith's auxiliary function
+    pub fn lambda(&self, w: Vector3) -> f32 {
+        let cos2_theta = w.z() * w.z();
+        if cos2_theta <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let alpha2_tan2_theta = ((self.alpha_x * w.x()) * (self.alpha_x * w.x()) + (self.alpha_y * w.y()) * (self.alpha_y * w.y())) / cos2_theta;
+
+        return 0.5 * (f32::sqrt(1.0 + alpha2_tan2_theta) - 1.0);
+    }
+
+    // Masking
+    pub fn g1(&self, w: Vector3) -> f32 {
+        return 1.0 / (1.0 + self.lambda(w));
+    }
+
+    // Height correlated masking-shadowing
+    pub fn g(&self, wo: Vector3, wi: Vector3) -> f32 {
+        return 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+    }
+
+    // Distribution of visible normals from w
+    pub fn d_visible(&self, w: Vector3, wm: Vector3) -> f32 {
+        let cos_theta = w.z().abs();
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+
+        return self.g1(w) / cos_theta * self.d(wm) * w.dot(wm).abs();
+    }
+
+    // Microfacet normal to bounce wo off, flat when smooth
+    pub fn sample_normal(&self, wo: Vector3) -> Vector3 {
+        if self.effectively_smooth() {
+            return Vector3::new(0.0, 0.0, 1.0);
+        }
+
+        return self.sample_wm(wo, rng::random_f32(), rng::random_f32());
+    }
+
+    // f * cos / pdf for a direction from sample_normal, without Fresnel
+    pub fn sample_weight(&self, wo: Vector3, wi: Vector3) -> f32 {
+        if self.effectively_smooth() {
+            return 1.0;
+        }
+
+        return self.g(wo, wi) / self.g1(wo);
+    }
+
+    // Visible normal sampling, Heitz 2018
+    pub fn sample_wm(&self, w: Vector3, u1: f32, u2: f32) -> Vector3 {
+        // Stretch to the hemisphere configuration
+        let mut wh = Vector3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).normalize();
+        if wh.z() < 0.0 {
+            wh = -1.0 * wh;
+        }
+
+        let t1 = if wh.z() < 0.99999 { Vector3::new(0.0, 0.0, 1.0).cross(wh).normalize() } else { Vector3::new(1.0, 0.0, 0.0) };
+        let t2 = wh.cross(t1);
+
+        // Uniform disk, then warp to the visible part
+        let r = f32::sqrt(u1);
+        let phi = 2.0 * f32::consts::PI * u2;
+        let px = r * f32::cos(phi);
+        let mut py = r * f32::sin(phi);
+        let h = f32::sqrt(1.0 - px * px);
+        let s = 0.5 * (1.0 + wh.z());
+        py = (1.0 - s) * h + s * py;
+
+        let pz = f32::sqrt(f32::max(0.0, 1.0 - px * px - py * py));
+        let nh = px * t1 + py * t2 + pz * wh;
+
+        // Unstretch
+        return Vector3::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), f32::max(1e-6, nh.z())).normalize();
+    }
+}
+
+// Schlick's approximation for a coloured F0
+pub fn fresnel_schlick(cos_theta: f32, f0: Vector3) -> Vector3 {
+    let weight = f32::powi(1.0 - cos_theta.clamp(0.0, 1.0), 5);
+    return f0 + weight * (Vector3::new(1.0, 1.0, 1.0) - f0);
+}
+
+// Fresnel reflectance of a conductor with complex IOR eta + ik, for one channel
+pub fn fresnel_conductor(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let cos2 = cos_theta_i * cos_theta_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = f32::sqrt(f32::max(0.0, t0 * t0 + 4.0 * eta2 * k2));
+    let t1 = a2_plus_b2 + cos2;
+    let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+    let t2 = 2.0 * cos_theta_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    return 0.5 * (rp + rs);
+}
+
+pub fn fresnel_conductor_rgb(cos_theta_i: f32, eta: Vector3, k: Vector3) -> Vector3 {
+    return Vector3::new(
+        fresnel_conductor(cos_theta_i, eta.x(), k.x()),
+        fresnel_conductor(cos_theta_i, eta.y(), k.y()),
+        fresnel_conductor(cos_theta_i, eta.z(), k.z()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Midpoint rule over the upper hemisphere in cos theta and phi
+    fn integrate_hemisphere(f: impl Fn(Vector3) -> f32) -> f32 {
+        let (n_mu, n_phi) = (400, 200);
+        let mut sum = 0.0;
+
+        for i in 0..n_mu {
+            let mu = (i as f32 + 0.5) / n_mu as f32;
+            let sin_theta = f32::sqrt(1.0 - mu * mu);
+            for j in 0..n_phi {
+                let phi = 2.0 * f32::consts::PI * (j as f32 + 0.5) / n_phi as f32;
+                sum += f(Vector3::new(sin_theta * f32::cos(phi), sin_theta * f32::sin(phi), mu));
+            }
+        }
+
+        return sum * 2.0 * f32::consts::PI / (n_mu * n_phi) as f32;
+    }
+
+    #[test]
+    fn projected_normals_cover_the_surface() {
+        for alpha in [0.3, 0.6, 1.0] {
+            let distribution = TrowbridgeReitz::new(alpha, alpha);
+            let area = integrate_hemisphere(|wm| distribution.d(wm) * wm.z());
+            assert!((area - 1.0).abs() < 0.01, "alpha {} gave {}", alpha, area);
+        }
+    }
+
+    #[test]
+    fn visible_normals_are_normalised() {
+        let distribution = TrowbridgeReitz::new(0.5, 0.5);
+        for wo in [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.6, 0.0, 0.8), Vector3::new(0.0, 0.95, 0.312_25).normalize()] {
+            // Only normals facing wo are visible
+            let total = integrate_hemisphere(|wm| if wo.dot(wm) > 0.0 { distribution.d_visible(wo, wm) } else { 0.0 });
+            assert!((total - 1.0).abs() < 0.01, "wo {:?} gave {}", wo.z(), total);
+        }
+    }
+
+    #[test]
+    fn masking_is_at_most_one() {
+        let distribution = TrowbridgeReitz::new(0.7, 0.7);
+        assert!((distribution.g1(Vector3::new(0.0, 0.0, 1.0)) - 1.0).abs() < 1e-6);
+
+        for z in [0.1, 0.4, 0.9] {
+            let w = Vector3::new(f32::sqrt(1.0 - z * z), 0.0, z);
+            let g1 = distribution.g1(w);
+            assert!(0.0 < g1 && g1 <= 1.0);
+            assert!(distribution.g(w, w) <= g1);
+            assert!(distribution.sample_weight(w, w) <= 1.0);
+        }
+    }
+
+    #[test]
+    fn sampled_normals_face_the_viewer() {
+        let distribution = TrowbridgeReitz::new(0.4, 0.4);
+        let wo = Vector3::new(0.8, 0.0, 0.6);
+        for i in 0..8 {
+            for j in 0..8 {
+                let wm = distribution.sample_wm(wo, (i as f32 + 0.5) / 8.0, (j as f32 + 0.5) / 8.0);
+                assert!((wm.norm() - 1.0).abs() < 1e-4);
+                assert!(wm.z() > 0.0 && wo.dot(wm) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_matches_closed_forms() {
+        let (eta, k) = (0.2, 3.9);
+        let normal = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - normal).abs() < 1e-5);
+        assert!((fresnel_conductor(0.0, eta, k) - 1.0).abs() < 1e-5);
+
+        // No absorption is a dielectric
+        let dielectric = (0.5_f32 / 2.5) * (0.5 / 2.5);
+        assert!((fresnel_conductor(1.0, 1.5, 0.0) - dielectric).abs() < 1e-5);
+    }
+
+    #[test]
+    fn schlick_goes_from_f0_to_one() {
+        let f0 = Vector3::new(0.04, 0.5, 0.9);
+        assert!((fresnel_schlick(1.0, f0) - f0).norm() < 1e-6);
+        assert!((fresnel_schlick(0.0, f0) - Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-6);
+    }
+}
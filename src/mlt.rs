@@ -0,0 +1,251 @@
+// Primary sample space Metropolis light transport, Kelemen et al. 2002 by way of pbrt
+// The path tracer is left as it is, every random number it draws during a mutation comes from
+// a vector of primary samples instead, and mutating that vector moves the whole path.
+// Chains live across passes, so bright paths found once keep getting explored
+use core::f32;
+use std::sync::Mutex;
+
+use crate::{integrator::{Integrator, PathIntegrator}, object_list::object_list::ObjectList, ray::ray::Ray, rng::{self, RandomSource, Xorshift32State}, vector3::Vector3};
+
+// Independent paths used to find the image brightness and to start the chains
+const BOOTSTRAP_SAMPLES: u32 = 4096;
+const CHAINS: u32 = 64;
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+// Standard deviation of a small step
+const SIGMA: f32 = 0.01;
+
+fn luminance(color: Vector3) -> f32 {
+    return (0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()).max(0.0);
+}
+
+#[derive(Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    last_modified: u64,
+    // Restored when a mutation is rejected
+    value_backup: f32,
+    modify_backup: u64,
+}
+
+// Samples are mutated lazily, only when the path actually asks for them
+struct MltSampler {
+    rng: Xorshift32State,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    last_large_step: u64,
+    large_step: bool,
+    index: usize,
+}
+
+impl MltSampler {
+    fn new(seed: u32) -> Self {
+        // Xorshift gets stuck on a zero seed
+        return Self { rng: Xorshift32State::new(seed.max(1)), samples: Vec::new(), iteration: 0, last_large_step: 0, large_step: true, index: 0 };
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        // The first one is always large, the samples start out as zeros
+        self.large_step = self.rng.next_scalar() < LARGE_STEP_PROBABILITY || self.iteration == 1;
+        self.index = 0;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.last_modified == self.iteration {
+                sample.value = sample.value_backup;
+                sample.last_modified = sample.modify_backup;
+            }
+        }
+
+        self.iteration -= 1;
+    }
+
+    // Box-Muller
+    fn normal(&mut self) -> f32 {
+        let u1 = self.rng.next_scalar().max(1e-7);
+        let u2 = self.rng.next_scalar();
+        return f32::sqrt(-2.0 * f32::ln(u1)) * f32::cos(2.0 * f32::consts::PI * u2);
+    }
+
+    // Catches a sample up with every mutation it missed
+    fn ensure_ready(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+
+        // A large step since it was last used replaced it with a fresh value
+        if self.samples[index].last_modified < self.last_large_step {
+            self.samples[index].value = self.rng.next_scalar();
+            self.samples[index].last_modified = self.last_large_step;
+        }
+
+        let sample = &mut self.samples[index];
+        sample.value_backup = sample.value;
+        sample.modify_backup = sample.last_modified;
+
+        if self.large_step {
+            self.samples[index].value = self.rng.next_scalar();
+        }
+
+        else {
+            // Small steps add up, so one that was skipped n times moves sqrt(n) as far
+            let steps = (self.iteration - self.samples[index].last_modified) as f32;
+            let offset = SIGMA * steps.sqrt() * self.normal();
+            let value = self.samples[index].value + offset;
+            self.samples[index].value = value - value.floor();
+        }
+
+        self.samples[index].last_modified = self.iteration;
+    }
+}
+
+impl RandomSource for MltSampler {
+    fn next_scalar(&mut self) -> f32 {
+        let index = self.index;
+        self.index += 1;
+        self.ensure_ready(index);
+
+        return self.samples[index].value;
+    }
+}
+
+struct Chain {
+    sampler: MltSampler,
+    color: Vector3,
+    pixel: (u32, u32),
+}
+
+struct MltState {
+    // Average luminance of the image
+    brightness: f32,
+    chains: Vec<Chain>,
+}
+
+pub struct MltIntegrator {
+    path: PathIntegrator,
+    // Set up on the first pass, once the image size is known
+    state: Mutex<Option<MltState>>,
+}
+
+impl MltIntegrator {
+    pub fn new(path: PathIntegrator) -> Self {
+        return Self { path, state: Mutex::new(None) };
+    }
+
+    // Picks a pixel and traces a path through it, all from the sampler
+    fn evaluate(&self, sampler: &mut MltSampler, world: &ObjectList, width: u32, height: u32, get_ray: &dyn Fn(u32, u32) -> Ray) -> (Vector3, (u32, u32)) {
+        return rng::with_source(sampler, || {
+            let col = ((rng::random_f32() * width as f32) as u32).min(width - 1);
+            let row = ((rng::random_f32() * height as f32) as u32).min(height - 1);
+            let ray = get_ray(col, row);
+
+            (self.path.ray_color(&ray, world), (col, row))
+        });
+    }
+
+    // Seeds are replayed, so a chain can start from any bootstrap path
+    fn bootstrap(&self, world: &ObjectList, width: u32, height: u32, get_ray: &dyn Fn(u32, u32) -> Ray) -> MltState {
+        let mut weights = Vec::with_capacity(BOOTSTRAP_SAMPLES as usize);
+        for seed in 1..=BOOTSTRAP_SAMPLES {
+            let mut sampler = MltSampler::new(seed);
+            sampler.start_iteration();
+            let (color, _) = self.evaluate(&mut sampler, world, width, height, get_ray);
+            weights.push(luminance(color));
+        }
+
+        let total: f32 = weights.iter().sum();
+        let brightness = total / BOOTSTRAP_SAMPLES as f32;
+        let mut chains = Vec::with_capacity(CHAINS as usize);
+
+        if total <= 0.0 {
+            return MltState { brightness, chains };
+        }
+
+        for _ in 0..CHAINS {
+            // Proportional to luminance
+            let target = rng::random_f32() * total;
+            let mut sum = 0.0;
+            let mut seed = BOOTSTRAP_SAMPLES;
+            for (index, weight) in weights.iter().enumerate() {
+                sum += weight;
+                if sum >= target && *weight > 0.0 {
+                    seed = index as u32 + 1;
+                    break;
+                }
+            }
+
+            let mut sampler = MltSampler::new(seed);
+            sampler.start_iteration();
+            let (color, pixel) = self.evaluate(&mut sampler, world, width, height, get_ray);
+            sampler.accept();
+
+            chains.push(Chain { sampler, color, pixel });
+        }
+
+        return MltState { brightness, chains };
+    }
+}
+
+impl Integrator for MltIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        return self.path.ray_color(ray, world);
+    }
+
+    fn render_pass(&self, world: &ObjectList, width: u32, height: u32, get_ray: &dyn Fn(u32, u32) -> Ray, splat: &mut dyn FnMut(u32, u32, Vector3)) -> bool {
+        if width == 0 || height == 0 {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let state = state.get_or_insert_with(|| self.bootstrap(world, width, height, get_ray));
+
+        // Black image
+        if state.chains.is_empty() {
+            return true;
+        }
+
+        let pixels = width * height;
+        let mutations_per_chain = pixels.div_ceil(state.chains.len() as u32);
+        let mutations_per_pixel = (mutations_per_chain * state.chains.len() as u32) as f32 / pixels as f32;
+        let scale = state.brightness / mutations_per_pixel;
+
+        for chain in &mut state.chains {
+            for _ in 0..mutations_per_chain {
+                chain.sampler.start_iteration();
+                let (color, pixel) = self.evaluate(&mut chain.sampler, world, width, height, get_ray);
+
+                let current = luminance(chain.color);
+                let proposed = luminance(color);
+                let accept = if current > 0.0 { (proposed / current).min(1.0) } else { 1.0 };
+
+                // Both states get splatted, weighted by how likely each is to be kept
+                if accept > 0.0 && proposed > 0.0 {
+                    splat(pixel.0, pixel.1, (accept * scale / proposed) * color);
+                }
+
+                if accept < 1.0 && current > 0.0 {
+                    splat(chain.pixel.0, chain.pixel.1, ((1.0 - accept) * scale / current) * chain.color);
+                }
+
+                if rng::random_f32() < accept {
+                    chain.sampler.accept();
+                    chain.color = color;
+                    chain.pixel = pixel;
+                }
+
+                else {
+                    chain.sampler.reject();
+                }
+            }
+        }
+
+        return true;
+    }
+}
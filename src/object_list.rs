@@ -20,8 +20,9 @@ pub mod object_list {
             let mut hit_anything  = None;
             let mut closest_hit = ray_t.max;
 
-            for object in &self.objects {
-                if let Some(hit) = object.hit(ray, Interval::new(ray_t.min, closest_hit)) {
+            for (index, object) in self.objects.iter().enumerate() {
+                if let Some(mut hit) = object.hit(ray, Interval::new(ray_t.min, closest_hit)) {
+                    hit.object_id = index as u32;
                     hit_anything = Some(hit.clone());
                     closest_hit = hit.t;
                     //*record = temp_rec.clone();
@@ -29,5 +30,18 @@ pub mod object_list {
             }
             return hit_anything;
         }
+
+        fn transmittance(&self, ray: &ray::Ray, ray_t: Interval) -> f32 {
+            let mut transmittance = 1.0;
+
+            for object in &self.objects {
+                transmittance *= object.transmittance(ray, Interval::new(ray_t.min, ray_t.max));
+                if transmittance == 0.0 {
+                    break;
+                }
+            }
+
+            return transmittance;
+        }
     }
 }
\ No newline at end of file
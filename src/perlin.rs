@@ -0,0 +1,143 @@
+// Perlin noise as in the second book, with gradient vectors and turbulence
+use crate::rng::Xorshift32State;
+use crate::vector3::Vector3;
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin {
+    rand_vec: Vec<Vector3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    // Tables come from their own RNG so the same seed always gives the same pattern
+    pub fn new(rng: &mut Xorshift32State) -> Self {
+        let rand_vec = (0..POINT_COUNT).map(|_| {
+            loop {
+                // [0, 1] to [-1, 1]
+                let p = 2.0 * Vector3::from_v128(rng.next_vec()) - Vector3::new(1.0, 1.0, 1.0);
+                let len_sqr = p.norm_squared();
+
+                if 1e-8 < len_sqr && len_sqr <= 1.0 {
+                    break p.normalize();
+                }
+            }
+        }).collect();
+
+        return Self {
+            rand_vec,
+            perm_x: Self::generate_perm(rng),
+            perm_y: Self::generate_perm(rng),
+            perm_z: Self::generate_perm(rng),
+        };
+    }
+
+    pub fn noise(&self, point: Vector3) -> f32 {
+        let u = point.x() - point.x().floor();
+        let v = point.y() - point.y().floor();
+        let w = point.z() - point.z().floor();
+
+        let i = point.x().floor() as i32;
+        let j = point.y().floor() as i32;
+        let k = point.z().floor() as i32;
+
+        let mut c = [[[Vector3::default(); 2]; 2]; 2];
+
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    *corner = self.rand_vec[
+                        self.perm_x[((i + di as i32) & 255) as usize] ^
+                        self.perm_y[((j + dj as i32) & 255) as usize] ^
+                        self.perm_z[((k + dk as i32) & 255) as usize]
+                    ];
+                }
+            }
+        }
+
+        return Self::perlin_interp(&c, u, v, w);
+    }
+
+    // Sum of noise octaves, each one at double the frequency and half the weight
+    pub fn turbulence(&self, point: Vector3, depth: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut temp_point = point;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(temp_point);
+            weight *= 0.5;
+            temp_point = 2.0 * temp_point;
+        }
+
+        return accum.abs();
+    }
+
+    fn generate_perm(rng: &mut Xorshift32State) -> Vec<usize> {
+        let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+
+        // Fisher-Yates
+        for i in (1..POINT_COUNT).rev() {
+            let target = ((rng.next_scalar() * (i + 1) as f32) as usize).min(i);
+            perm.swap(i, target);
+        }
+
+        return perm;
+    }
+
+    fn perlin_interp(c: &[[[Vector3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
+        // Hermite smoothing
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+        let mut accum = 0.0;
+
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f32, j as f32, k as f32);
+                    let weight = Vector3::new(u - fi, v - fj, w - fk);
+
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                           * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                           * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                           * corner.dot(weight);
+                }
+            }
+        }
+
+        return accum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_at_lattice_points() {
+        let perlin = Perlin::new(&mut Xorshift32State::seeded(7));
+        for point in [Vector3::new(0.0, 0.0, 0.0), Vector3::new(3.0, -2.0, 5.0), Vector3::new(-7.0, 1.0, 255.0)] {
+            assert!(perlin.noise(point).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let perlin = Perlin::new(&mut Xorshift32State::seeded(7));
+        let mut rng = Xorshift32State::seeded(1);
+        for _ in 0..1000 {
+            let point = Vector3::new(rng.next_scalar() * 50.0, rng.next_scalar() * 50.0, rng.next_scalar() * 50.0);
+            assert!(perlin.noise(point).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let (a, b) = (Perlin::new(&mut Xorshift32State::seeded(3)), Perlin::new(&mut Xorshift32State::seeded(3)));
+        let point = Vector3::new(1.3, 4.7, -2.2);
+        assert_eq!(a.noise(point), b.noise(point));
+    }
+}
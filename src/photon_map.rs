@@ -0,0 +1,316 @@
+// Progressive photon mapping for caustics, the probabilistic formulation of Knaus and Zwicker 2011
+// Every pass shoots a fresh photon map with a slightly smaller radius, and the camera's running
+// average over passes does the rest, so nothing has to be kept per pixel.
+// Photons only store caustics, light that went through specular bounces before landing on a
+// diffuse surface. Everything else is path traced, and camera paths skip light that reaches a
+// diffuse surface through specular bounces since the photons already carry it.
+// Volumes count as diffuse but don't keep photons, so light reaching them is path traced
+use core::f32;
+use std::collections::HashMap;
+
+use crate::{integrator::{self, Integrator, LightList, MediumStack}, interval::Interval, object_list::object_list::ObjectList, ray::ray::Ray, rng, scene_object::scene_object::{HitRecord, SceneObject}, vector3::Vector3, vector_utils::{random_vec3_unit, Onb}};
+
+// How quickly the radius shrinks, closer to 1 keeps it wider for longer
+const RADIUS_ALPHA: f32 = 2.0 / 3.0;
+
+struct Photon {
+    point: Vector3,
+    // Back towards where the photon came from
+    direction: Vector3,
+    power: Vector3,
+}
+
+// Uniform hash grid with cells as wide as the lookup radius, a lookup checks the 27 around it
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+    radius: f32,
+}
+
+impl PhotonMap {
+    fn new(photons: Vec<Photon>, radius: f32) -> Self {
+        let mut cells: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+        for (index, photon) in photons.iter().enumerate() {
+            cells.entry(Self::cell(photon.point, radius)).or_default().push(index);
+        }
+
+        return Self { photons, cells, radius };
+    }
+
+    fn cell(point: Vector3, radius: f32) -> (i32, i32, i32) {
+        return ((point.x() / radius).floor() as i32, (point.y() / radius).floor() as i32, (point.z() / radius).floor() as i32);
+    }
+
+    // Reflected radiance from the photons within the radius, with a constant kernel
+    fn estimate(&self, hit: &HitRecord, wo: Vector3) -> Vector3 {
+        let mut flux = Vector3::new(0.0, 0.0, 0.0);
+        let (i, j, k) = Self::cell(hit.point, self.radius);
+        let radius_squared = self.radius * self.radius;
+
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let Some(indices) = self.cells.get(&(i + di, j + dj, k + dk)) else {
+                        continue;
+                    };
+
+                    for &index in indices {
+                        let photon = &self.photons[index];
+                        if (photon.point - hit.point).norm_squared() > radius_squared {
+                            continue;
+                        }
+
+                        // evaluate includes the cosine, the photon power already has it
+                        let cos_theta = hit.normal.dot(photon.direction);
+                        if cos_theta <= 0.0 {
+                            continue;
+                        }
+
+                        if let Some((f, _)) = hit.material.evaluate(hit, wo, photon.direction) {
+                            flux += (1.0 / cos_theta) * f.component_mul(photon.power);
+                        }
+                    }
+                }
+            }
+        }
+
+        return flux / (f32::consts::PI * radius_squared);
+    }
+}
+
+// Diffuse means anything evaluate works for, the rest bounces photons along
+fn is_diffuse(hit: &HitRecord, wo: Vector3) -> bool {
+    return hit.material.evaluate(hit, wo, wo).is_some();
+}
+
+pub struct PhotonMappingIntegrator {
+    max_depth: u32,
+    photon_count: u32,
+    initial_radius: f32,
+    pass: u32,
+    lights: LightList,
+    // Bounding sphere around everything that can focus light
+    casters: Option<(Vector3, f32)>,
+    map: Option<PhotonMap>,
+}
+
+impl PhotonMappingIntegrator {
+    pub fn new(max_depth: u32, photon_count: u32, radius: f32) -> Self {
+        return Self {
+            max_depth,
+            photon_count: photon_count.max(1),
+            initial_radius: if radius > 0.0 { radius } else { 0.05 },
+            pass: 0,
+            lights: LightList::default(),
+            casters: None,
+            map: None,
+        };
+    }
+
+    // r_i^2 = r_1^2 * prod (k + alpha) / (k + 1)
+    fn radius(&self) -> f32 {
+        let mut radius_squared = self.initial_radius * self.initial_radius;
+        for k in 1..self.pass {
+            radius_squared *= (k as f32 + RADIUS_ALPHA) / (k as f32 + 1.0);
+        }
+
+        return radius_squared.sqrt();
+    }
+
+    // Box around every non diffuse, non emissive object, as a sphere
+    fn find_casters(world: &ObjectList) -> Option<(Vector3, f32)> {
+        let mut bounds: Option<(Vector3, Vector3)> = None;
+
+        for object in &world.objects {
+            let (Some((min, max)), Some(hit)) = (object.bounds(), object.sample_surface()) else {
+                continue;
+            };
+
+            if hit.material.is_emissive() || is_diffuse(&hit, hit.normal) {
+                continue;
+            }
+
+            bounds = Some(match bounds {
+                Some((lo, hi)) => (
+                    Vector3::new(lo.x().min(min.x()), lo.y().min(min.y()), lo.z().min(min.z())),
+                    Vector3::new(hi.x().max(max.x()), hi.y().max(max.y()), hi.z().max(max.z())),
+                ),
+                None => (min, max),
+            });
+        }
+
+        let (min, max) = bounds?;
+        return Some((0.5 * (min + max), 0.5 * (max - min).norm()));
+    }
+
+    // Sky photons are aimed at the casters from a disk just outside their bounding sphere
+    // The ones that couldn't have seen the sky from there are dropped
+    fn emit_from_sky(&self, world: &ObjectList, centre: Vector3, radius: f32) -> Option<(Ray, Vector3)> {
+        let direction = random_vec3_unit();
+        let frame = Onb::new(direction);
+
+        let r = radius * f32::sqrt(rng::random_f32());
+        let phi = 2.0 * f32::consts::PI * rng::random_f32();
+        let origin = centre - radius * direction + frame.to_world(Vector3::new(r * f32::cos(phi), r * f32::sin(phi), 0.0));
+
+        let towards_sky = Ray::new(origin, -1.0 * direction);
+        if world.hit(&towards_sky, Interval::new(0.001, f32::INFINITY)).is_some() {
+            return None;
+        }
+
+        // Radiance times disk area over the density of the direction
+        let power = (f32::consts::PI * radius * radius * 4.0 * f32::consts::PI) * integrator::background(&towards_sky);
+
+        return Some((Ray::new(origin, direction), power));
+    }
+
+    fn emit_from_light(&self) -> Option<(Ray, Vector3)> {
+        let (hit, pdf) = self.lights.sample()?;
+        let direction = integrator::cosine_direction(hit.normal);
+
+        // Cosine over its density is pi
+        let power = (f32::consts::PI / pdf) * hit.material.emitted(&hit);
+
+        return Some((Ray::new(hit.point, direction), power));
+    }
+
+    // Follows a photon through specular bounces, storing it where it first lands on something diffuse
+    fn trace_photon(&self, world: &ObjectList, ray: Ray, power: Vector3, photons: &mut Vec<Photon>) {
+        let mut ray = ray;
+        let mut power = power;
+        let mut media = MediumStack::default();
+
+        for bounce in 0..self.max_depth {
+            let Some(hit) = world.hit(&ray, Interval::new(0.001, f32::INFINITY)) else {
+                return;
+            };
+
+            power = power.component_mul(media.transmittance(hit.t));
+
+            let wo = -1.0 * ray.direction();
+            if is_diffuse(&hit, wo) {
+                // Straight from the light is direct lighting, the path tracer has it
+                // Volumes stop photons without keeping them, the estimate only works on surfaces
+                if bounce > 0 && !hit.material.is_phase_function() {
+                    photons.push(Photon { point: hit.point, direction: wo, power });
+                }
+
+                return;
+            }
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Vector3::default();
+            if !hit.material.scatter(&ray, &hit, &mut attenuation, &mut scattered) {
+                return;
+            }
+
+            // Photons carry the film weight in their power
+            if let Some(weight) = integrator::carry_wavelength(&ray, &mut scattered) {
+                attenuation = attenuation.component_mul(weight);
+            }
+
+            power = power.component_mul(attenuation);
+            media.update(&hit, &scattered);
+            ray = scattered;
+        }
+    }
+}
+
+impl Integrator for PhotonMappingIntegrator {
+    fn preprocess(&mut self, world: &ObjectList) {
+        if self.pass == 0 {
+            self.lights = LightList::new(world);
+            self.casters = Self::find_casters(world);
+        }
+
+        self.pass += 1;
+
+        // Nothing to focus light, so no caustics
+        let Some((centre, radius)) = self.casters else {
+            self.map = None;
+            return;
+        };
+
+        // Half the photons come from lights when there are any
+        let light_fraction = if self.lights.is_empty() { 0.0 } else { 0.5 };
+        let mut photons = Vec::new();
+
+        for _ in 0..self.photon_count {
+            let emitted = if rng::random_f32() < light_fraction {
+                self.emit_from_light().map(|(ray, power)| (ray, power / light_fraction))
+            } else {
+                self.emit_from_sky(world, centre, radius).map(|(ray, power)| (ray, power / (1.0 - light_fraction)))
+            };
+
+            if let Some((ray, power)) = emitted {
+                self.trace_photon(world, ray, power / self.photon_count as f32, &mut photons);
+            }
+        }
+
+        self.map = Some(PhotonMap::new(photons, self.radius()));
+    }
+
+    fn ray_color(&self, ray: &Ray, world: &ObjectList) -> Vector3 {
+        let mut radiance = Vector3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        // Set when the path gathered photons then went specular, the light it finds next is a caustic
+        // A volume in between means the photons never got there, so the path keeps that light
+        let mut gathered_last = false;
+        let mut caustic = false;
+        let mut media = MediumStack::default();
+        let mut film = Vector3::new(1.0, 1.0, 1.0);
+
+        for _ in 0..self.max_depth {
+            let Some(hit) = world.hit(&ray, Interval::new(0.001, f32::INFINITY)) else {
+                if !caustic {
+                    radiance += film.component_mul(throughput).component_mul(integrator::background(&ray));
+                }
+
+                break;
+            };
+
+            throughput = throughput.component_mul(media.transmittance(hit.t));
+
+            if hit.material.is_emissive() && !caustic {
+                radiance += film.component_mul(throughput).component_mul(hit.material.emitted(&hit));
+            }
+
+            let wo = -1.0 * ray.direction();
+            let diffuse = is_diffuse(&hit, wo);
+            let gathers = diffuse && !hit.material.is_phase_function();
+            if gathers {
+                if let Some(map) = &self.map {
+                    radiance += film.component_mul(throughput).component_mul(map.estimate(&hit, wo));
+                }
+            }
+
+            let mut scattered = Ray::default();
+            let mut attenuation = Vector3::default();
+            if !hit.material.scatter(&ray, &hit, &mut attenuation, &mut scattered) {
+                // Absorbed by something other than a light shows the sky, same as the path tracer
+                if !hit.material.is_emissive() && !caustic {
+                    radiance += film.component_mul(throughput).component_mul(integrator::background(&ray));
+                }
+
+                break;
+            }
+
+            if let Some(weight) = integrator::carry_wavelength(&ray, &mut scattered) {
+                film = weight;
+            }
+
+            // Stays set through a whole chain of specular bounces
+            caustic = !diffuse && gathered_last;
+            if diffuse {
+                gathered_last = gathers;
+            }
+
+            throughput = throughput.component_mul(attenuation);
+            media.update(&hit, &scattered);
+            ray = scattered;
+        }
+
+        return radiance;
+    }
+}
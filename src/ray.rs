@@ -1,10 +1,22 @@
 pub mod ray {
     use crate::vector3::Vector3;
 
-    #[derive(Default)]
+    // Offset rays for the neighbouring pixels in x and y, used to find texture footprints
+    #[derive(Clone, Copy)]
+    pub struct RayDifferential {
+        pub rx_origin: Vector3,
+        pub rx_direction: Vector3,
+        pub ry_origin: Vector3,
+        pub ry_direction: Vector3,
+    }
+
+    #[derive(Clone, Default)]
     pub struct Ray {
         origin: Vector3,
         direction: Vector3,
+        differentials: Option<RayDifferential>,
+        // Hero wavelength in nm, None while the path is still RGB
+        wavelength: Option<f32>,
     }
 
     impl Ray {
@@ -16,10 +28,33 @@ pub mod ray {
             self.direction
         }
 
+        pub fn differentials(&self) -> Option<RayDifferential> {
+            self.differentials
+        }
+
+        pub fn wavelength(&self) -> Option<f32> {
+            self.wavelength
+        }
+
+        pub fn set_wavelength(&mut self, wavelength: Option<f32>) {
+            self.wavelength = wavelength;
+        }
+
         pub fn new(origin:Vector3, direction: Vector3) -> Ray {
             Ray{
                 origin,
                 direction,
+                differentials: None,
+                wavelength: None,
+            }
+        }
+
+        pub fn with_differentials(origin: Vector3, direction: Vector3, differentials: Option<RayDifferential>) -> Ray {
+            Ray{
+                origin,
+                direction,
+                differentials,
+                wavelength: None,
             }
         }
 
@@ -27,4 +62,4 @@ pub mod ray {
             return self.origin + t * self.direction;
         }
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,25 @@
 // xorshift code straight from Wikipedia, but extra rusty
 // It is in fact not unused, WASM SIMD requires unsafe. Compiler is worong here. Again.
 #![allow(unused_unsafe)]
-use std::{arch::wasm32::{f32x4_convert_u32x4, f32x4_div, f32x4_splat, u32x4, u32x4_shl, u32x4_shr, v128, v128_xor}, u32};
+use std::{arch::wasm32::{f32x4, f32x4_convert_u32x4, f32x4_div, f32x4_splat, u32x4, u32x4_shl, u32x4_shr, v128, v128_xor}, cell::Cell, u32};
 
 use crate::RNG;
 
+// Anything that hands out numbers in [0, 1], so samplers can stand in for the xorshift
+pub trait RandomSource {
+    fn next_scalar(&mut self) -> f32;
+
+    fn next_vec(&mut self) -> v128 {
+        let (a, b, c, d) = (self.next_scalar(), self.next_scalar(), self.next_scalar(), self.next_scalar());
+        return unsafe { f32x4(a, b, c, d) };
+    }
+}
+
+thread_local! {
+    // Replaces RNG while set, only ever points at the source with_source is borrowing
+    static SOURCE: Cell<Option<*mut dyn RandomSource>> = Cell::new(None);
+}
+
 pub struct Xorshift32State {
     a: u32,
     vec: v128,
@@ -12,9 +27,22 @@ pub struct Xorshift32State {
 
 impl Xorshift32State {
     // Creates a new Xorshift32State struct
-    // Seed may not be zero
+    // Seed may not be zero, lanes that wrap around to zero are bumped to one
     pub fn new(seed: u32) -> Self {
-        return Self { a: seed, vec: u32x4(seed, seed + 1, seed + 2, seed + 3) };
+        let lane = |offset: u32| seed.wrapping_add(offset).max(1);
+        return Self { a: seed, vec: u32x4(lane(0), lane(1), lane(2), lane(3)) };
+    }
+
+    // For seeds that come from users, any value works and nearby seeds look nothing alike
+    pub fn seeded(seed: u32) -> Self {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9);
+            return splitmix32(state).max(1);
+        };
+
+        let a = next();
+        return Self { a, vec: u32x4(next(), next(), next(), next()) };
     }
 
     pub fn next_scalar(&mut self) -> f32 {
@@ -44,11 +72,121 @@ impl Xorshift32State {
     }
 }
 
+// SplitMix style finaliser, zero only for zero
+fn splitmix32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x21f0_aaad);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x735a_2d97);
+    x ^= x >> 15;
+
+    return x;
+}
+
+impl RandomSource for Xorshift32State {
+    fn next_scalar(&mut self) -> f32 {
+        return Xorshift32State::next_scalar(self);
+    }
+
+    fn next_vec(&mut self) -> v128 {
+        return Xorshift32State::next_vec(self);
+    }
+}
+
+// Puts the previous source back when with_source is done, even if f panics
+struct RestoreSource(Option<*mut dyn RandomSource>);
+
+impl Drop for RestoreSource {
+    fn drop(&mut self) {
+        SOURCE.with(|installed| installed.set(self.0));
+    }
+}
+
+// Runs f with every random number drawn from source
+// Only a pointer goes in the slot, so installing one per pixel sample doesn't allocate
+pub fn with_source<R>(source: &mut (dyn RandomSource + 'static), f: impl FnOnce() -> R) -> R {
+    let _restore = RestoreSource(SOURCE.with(|installed| installed.replace(Some(source as *mut dyn RandomSource))));
+    return f();
+}
 
 pub fn random_f32() -> f32 {
-    RNG.with(|rng| rng.borrow_mut().next_scalar())
+    return match SOURCE.with(|installed| installed.get()) {
+        // The borrow with_source holds keeps it alive and unaliased until it's taken out again
+        Some(source) => unsafe { (*source).next_scalar() },
+        None => RNG.with(|rng| rng.borrow_mut().next_scalar()),
+    };
 }
 
 pub fn random_v128() -> v128 {
-    RNG.with(|rng| rng.borrow_mut().next_vec())
-}
\ No newline at end of file
+    return match SOURCE.with(|installed| installed.get()) {
+        Some(source) => unsafe { (*source).next_vec() },
+        None => RNG.with(|rng| rng.borrow_mut().next_vec()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::arch::wasm32::f32x4_extract_lane;
+
+    use super::*;
+
+    #[test]
+    fn largest_seed_does_not_overflow() {
+        let mut rng = Xorshift32State::new(u32::MAX);
+        let value = rng.next_scalar();
+        assert!((0.0..=1.0).contains(&value));
+    }
+
+    #[test]
+    fn seeded_lanes_are_never_zero() {
+        for seed in [0, 1, 0xFFFF_FFFD, 0xFFFF_FFFE, u32::MAX] {
+            let mut rng = Xorshift32State::seeded(seed);
+            assert_ne!(rng.a, 0);
+
+            // A zero lane stays zero forever
+            let mut lanes = [0.0; 4];
+            for _ in 0..4 {
+                let v = rng.next_vec();
+                lanes[0] += f32x4_extract_lane::<0>(v);
+                lanes[1] += f32x4_extract_lane::<1>(v);
+                lanes[2] += f32x4_extract_lane::<2>(v);
+                lanes[3] += f32x4_extract_lane::<3>(v);
+            }
+
+            assert!(lanes.iter().all(|&lane| lane > 0.0));
+        }
+    }
+
+    // Counts how many numbers it handed out
+    struct Counter(u32);
+
+    impl RandomSource for Counter {
+        fn next_scalar(&mut self) -> f32 {
+            self.0 += 1;
+            return 0.5;
+        }
+    }
+
+    #[test]
+    fn with_source_nests_and_puts_the_old_one_back() {
+        let (mut outer, mut inner) = (Counter(0), Counter(0));
+
+        let value = with_source(&mut outer, || {
+            random_f32();
+            with_source(&mut inner, || random_f32() + random_f32());
+            return random_f32();
+        });
+        random_f32();
+
+        assert_eq!(value, 0.5);
+        assert_eq!((outer.0, inner.0), (2, 2));
+    }
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let (mut a, mut b) = (Xorshift32State::seeded(42), Xorshift32State::seeded(42));
+        for _ in 0..16 {
+            assert_eq!(a.next_scalar(), b.next_scalar());
+        }
+    }
+}
@@ -0,0 +1,386 @@
+// Samplers decide the random numbers for each sample of each pixel
+// The camera installs one as the random source around every pixel sample, so the lens, the
+// integrator and every material draw from it without knowing. Each draw is the next dimension
+use crate::{blue_noise, rng::{RandomSource, Xorshift32State}, shared_mem::SharedMem};
+
+// Largest f32 below one
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+pub trait Sampler: Send + Sync {
+    // Dimensions start over at zero for every sample
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32);
+    fn get_1d(&mut self) -> f32;
+}
+
+impl RandomSource for Box<dyn Sampler> {
+    fn next_scalar(&mut self) -> f32 {
+        return self.get_1d();
+    }
+}
+
+// 0 = independent, 1 = stratified, 2 = Halton, 3 = Owen scrambled Sobol, 4 = blue noise
+pub fn from_settings(settings: &SharedMem) -> Box<dyn Sampler> {
+    let samples_per_pixel = settings.samples_per_pixel.max(1);
+
+    return match settings.sampler {
+        1 => Box::new(StratifiedSampler::new(samples_per_pixel)),
+        2 => Box::new(HaltonSampler::new()),
+        3 => Box::new(SobolSampler::new(samples_per_pixel)),
+        4 => Box::new(BlueNoiseSampler::new()),
+        _ => Box::new(IndependentSampler::new()),
+    };
+}
+
+// SplitMix style finaliser
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+
+    return v;
+}
+
+fn hash(x: u32, y: u32, a: u32, b: u32) -> u32 {
+    let pixel = ((x as u64) << 32) | y as u64;
+    let rest = ((a as u64) << 32) | b as u64;
+
+    return mix_bits(mix_bits(pixel) ^ rest) as u32;
+}
+
+fn to_unit(bits: u32) -> f32 {
+    return (bits as f32 * (1.0 / 4294967296.0)).min(ONE_MINUS_EPSILON);
+}
+
+// Element i of a random permutation of 0..length picked by seed, Kensler 2013
+fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // Cycle walking until it lands inside the range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < length {
+            break;
+        }
+    }
+
+    return (i.wrapping_add(seed)) % length;
+}
+
+// Plain random numbers, but the same ones for the same pixel and sample
+pub struct IndependentSampler {
+    rng: Xorshift32State,
+}
+
+impl IndependentSampler {
+    pub fn new() -> Self {
+        return Self { rng: Xorshift32State::new(0xBAD5EED) };
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        // Xorshift gets stuck on a zero seed
+        self.rng = Xorshift32State::new(hash(x, y, sample_index, 0).max(1));
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        return self.rng.next_scalar().min(ONE_MINUS_EPSILON);
+    }
+}
+
+// One jittered stratum per sample in every dimension, shuffled differently per dimension
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        return Self { samples_per_pixel, pixel: (0, 0), sample_index: 0, dimension: 0 };
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (x, y) = self.pixel;
+        let seed = hash(x, y, self.dimension, 1);
+        // Past the sample count it starts another round of strata
+        let round = self.sample_index / self.samples_per_pixel;
+        let stratum = permutation_element(self.sample_index % self.samples_per_pixel, self.samples_per_pixel, seed ^ round);
+        let jitter = to_unit(hash(x, y, self.dimension, self.sample_index.wrapping_add(2)));
+
+        self.dimension += 1;
+
+        return ((stratum as f32 + jitter) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON);
+    }
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed: u64 = 0;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        // Stays below base times the index, so it never actually wraps
+        reversed = reversed.wrapping_mul(base as u64) + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    return ((reversed as f64 * inv_base_n) as f32).min(ONE_MINUS_EPSILON);
+}
+
+// Halton sequence with a random shift per pixel and dimension (Cranley-Patterson rotation)
+// Dimensions past the prime table reuse the primes with a different shift
+pub struct HaltonSampler {
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl HaltonSampler {
+    pub fn new() -> Self {
+        return Self { pixel: (0, 0), sample_index: 0, dimension: 0 };
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let base = PRIMES[self.dimension as usize % PRIMES.len()];
+        let shift = to_unit(hash(self.pixel.0, self.pixel.1, self.dimension, 3));
+        let value = radical_inverse(base, self.sample_index) + shift;
+
+        self.dimension += 1;
+
+        return (value - value.floor()).min(ONE_MINUS_EPSILON);
+    }
+}
+
+// First two Sobol dimensions, the first is just the bits reversed
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut direction: u32 = 1 << 31;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            y ^= direction;
+        }
+
+        direction ^= direction >> 1;
+    }
+
+    return (index.reverse_bits(), y);
+}
+
+// Hash based nested uniform scrambling, Laine and Karras 2011
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    v = v.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20_adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x0552_6c56);
+    v ^= v.wrapping_mul(0x53a2_2864);
+
+    return v.reverse_bits();
+}
+
+// Owen scrambled 2D Sobol points padded together, like pbrt's padded Sobol sampler
+// Each pair of dimensions shuffles the sample order on its own so pairs don't correlate
+pub struct SobolSampler {
+    samples_per_pixel: u32,
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u32,
+    // Second half of the current pair
+    pending: Option<f32>,
+}
+
+impl SobolSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        return Self { samples_per_pixel, pixel: (0, 0), sample_index: 0, dimension: 0, pending: None };
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.pending = None;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.dimension += 1;
+        if let Some(value) = self.pending.take() {
+            return value;
+        }
+
+        let (x, y) = self.pixel;
+        let seed = hash(x, y, self.dimension, 4);
+        // Sobol is only well distributed in powers of two, anything past the sample count keeps going
+        let index = if self.sample_index < self.samples_per_pixel {
+            permutation_element(self.sample_index, self.samples_per_pixel, seed)
+        } else {
+            self.sample_index
+        };
+
+        let (first, second) = sobol_2d(index);
+        self.pending = Some(to_unit(owen_scramble(second, hash(x, y, self.dimension, 5))));
+
+        return to_unit(owen_scramble(first, hash(x, y, self.dimension, 6)));
+    }
+}
+
+// Golden ratio, consecutive samples step through [0, 1) as evenly as they can
+const R1: f32 = 0.618_034;
+
+// For 1 spp previews, every dimension reads the blue noise mask at its own random offset so
+// neighbouring pixels get very different numbers. Later samples step each pixel along by the
+// golden ratio, which keeps the error blue over time too
+pub struct BlueNoiseSampler {
+    pixel: (u32, u32),
+    sample_index: u32,
+    dimension: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new() -> Self {
+        return Self { pixel: (0, 0), sample_index: 0, dimension: 0 };
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let offset = hash(0, 0, self.dimension, 7);
+        let (x, y) = (self.pixel.0.wrapping_add(offset), self.pixel.1.wrapping_add(offset >> 16));
+        // Jitter within the mask's step so the values aren't quantised
+        let jitter = to_unit(hash(self.pixel.0, self.pixel.1, self.dimension, 8)) / (blue_noise::SIZE * blue_noise::SIZE) as f32;
+        let value = blue_noise::value(x, y) + jitter + R1 * self.sample_index as f32;
+
+        self.dimension += 1;
+
+        return (value - value.floor()).min(ONE_MINUS_EPSILON);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every number a sampler gives one pixel, by sample then dimension
+    fn draw(sampler: &mut dyn Sampler, pixel: (u32, u32), samples: u32, dimensions: usize) -> Vec<Vec<f32>> {
+        return (0..samples).map(|index| {
+            sampler.start_pixel_sample(pixel.0, pixel.1, index);
+            return (0..dimensions).map(|_| sampler.get_1d()).collect();
+        }).collect();
+    }
+
+    #[test]
+    fn every_sampler_stays_below_one() {
+        assert_eq!(f32::from_bits(ONE_MINUS_EPSILON.to_bits() + 1), 1.0);
+
+        let samplers: Vec<Box<dyn Sampler>> = vec![
+            Box::new(IndependentSampler::new()),
+            Box::new(StratifiedSampler::new(16)),
+            Box::new(HaltonSampler::new()),
+            Box::new(SobolSampler::new(16)),
+            Box::new(BlueNoiseSampler::new()),
+        ];
+
+        for mut sampler in samplers {
+            for pixel in [(0, 0), (7, 3), (4095, 65535)] {
+                // Past the sample count too
+                for sample in draw(sampler.as_mut(), pixel, 40, 12) {
+                    assert!(sample.iter().all(|value| (0.0..1.0).contains(value)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_puts_one_sample_in_each_stratum_per_dimension() {
+        let samples_per_pixel = 16;
+        let mut sampler = StratifiedSampler::new(samples_per_pixel);
+
+        for pixel in [(0, 0), (13, 2)] {
+            let samples = draw(&mut sampler, pixel, samples_per_pixel, 8);
+            for dimension in 0..8 {
+                let mut strata: Vec<u32> = samples.iter().map(|sample| (sample[dimension] * samples_per_pixel as f32) as u32).collect();
+                strata.sort();
+                assert_eq!(strata, (0..samples_per_pixel).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_pairs_fill_every_elementary_interval() {
+        // 2^4 points, every way of splitting the square into 16 boxes of 2^-a by 2^-(4 - a)
+        let samples_per_pixel = 16;
+        let mut sampler = SobolSampler::new(samples_per_pixel);
+
+        for pixel in [(0, 0), (5, 9)] {
+            let samples = draw(&mut sampler, pixel, samples_per_pixel, 6);
+            for pair in [0, 2, 4] {
+                for a in 0..=4 {
+                    let mut boxes: Vec<(u32, u32)> = samples.iter().map(|sample| {
+                        ((sample[pair] * (1 << a) as f32) as u32, (sample[pair + 1] * (1 << (4 - a)) as f32) as u32)
+                    }).collect();
+                    boxes.sort();
+                    boxes.dedup();
+                    assert_eq!(boxes.len(), samples_per_pixel as usize, "pair {} split {}", pair, a);
+                }
+            }
+        }
+    }
+}
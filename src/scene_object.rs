@@ -1,7 +1,20 @@
 pub mod scene_object {
     use std::sync::Arc;
 
-    use crate::{interval::Interval, material::Material, ray::ray, vector3::Vector3};
+    use crate::{interval::Interval, material::Material, ray::ray::{self, RayDifferential}, vector3::Vector3, vector_utils::Onb};
+
+    // Screen space derivatives at a hit, from the ray differentials
+    #[derive(Clone, Copy, Default)]
+    pub struct SurfaceDifferentials {
+        pub dpdx: Vector3,
+        pub dpdy: Vector3,
+        pub dndx: Vector3,
+        pub dndy: Vector3,
+        pub dudx: f32,
+        pub dvdx: f32,
+        pub dudy: f32,
+        pub dvdy: f32,
+    }
 
     #[derive(Clone)]
     pub struct HitRecord {
@@ -9,7 +22,18 @@ pub mod scene_object {
         pub normal: Vector3,
         pub material: Arc<dyn Material>,
         pub t: f32,
+        pub u: f32,
+        pub v: f32,
         pub front_face: bool,
+        // Surface tangents along u and v, not normalised
+        pub dpdu: Vector3,
+        pub dpdv: Vector3,
+        // How the normal turns along u and v, flipped along with the normal
+        pub dndu: Vector3,
+        pub dndv: Vector3,
+        pub differentials: Option<SurfaceDifferentials>,
+        // Index in the world list, set by ObjectList
+        pub object_id: u32,
     }
 
     impl HitRecord {
@@ -31,12 +55,143 @@ pub mod scene_object {
                 normal: Vector3::new(0.0, 0.0, 0.0),
                 material,
                 t: 0.0,
-                front_face: false
+                u: 0.0,
+                v: 0.0,
+                front_face: false,
+                dpdu: Vector3::new(0.0, 0.0, 0.0),
+                dpdv: Vector3::new(0.0, 0.0, 0.0),
+                dndu: Vector3::new(0.0, 0.0, 0.0),
+                dndv: Vector3::new(0.0, 0.0, 0.0),
+                differentials: None,
+                object_id: 0,
+            }
+        }
+
+        // Tangent space around the current normal, u follows dpdu
+        pub fn tangent_frame(&self) -> Onb {
+            return Onb::from_tangent(self.normal, self.dpdu);
+        }
+
+        // Same as pbrt, intersect the offset rays with the tangent plane and solve for the UV derivatives
+        // dndu and dndv should already be flipped along with the normal
+        pub fn compute_differentials(&mut self, ray: &ray::Ray, dpdu: Vector3, dpdv: Vector3, dndu: Vector3, dndv: Vector3) {
+            let Some(rd) = ray.differentials() else {
+                self.differentials = None;
+                return;
+            };
+
+            let d = self.normal.dot(self.point);
+            let tx = (d - self.normal.dot(rd.rx_origin)) / self.normal.dot(rd.rx_direction);
+            let ty = (d - self.normal.dot(rd.ry_origin)) / self.normal.dot(rd.ry_direction);
+
+            // Grazing angles
+            if !tx.is_finite() || !ty.is_finite() {
+                self.differentials = None;
+                return;
             }
+
+            let dpdx = (rd.rx_origin + tx * rd.rx_direction) - self.point;
+            let dpdy = (rd.ry_origin + ty * rd.ry_direction) - self.point;
+
+            // Least squares fit of dp = dpdu * du + dpdv * dv
+            let ata00 = dpdu.dot(dpdu);
+            let ata01 = dpdu.dot(dpdv);
+            let ata11 = dpdv.dot(dpdv);
+            let mut inv_det = 1.0 / (ata00 * ata11 - ata01 * ata01);
+            if !inv_det.is_finite() {
+                inv_det = 0.0;
+            }
+
+            let atb0x = dpdu.dot(dpdx);
+            let atb1x = dpdv.dot(dpdx);
+            let atb0y = dpdu.dot(dpdy);
+            let atb1y = dpdv.dot(dpdy);
+
+            let dudx = (ata11 * atb0x - ata01 * atb1x) * inv_det;
+            let dvdx = (ata00 * atb1x - ata01 * atb0x) * inv_det;
+            let dudy = (ata11 * atb0y - ata01 * atb1y) * inv_det;
+            let dvdy = (ata00 * atb1y - ata01 * atb0y) * inv_det;
+
+            self.differentials = Some(SurfaceDifferentials {
+                dpdx,
+                dpdy,
+                dndx: dudx * dndu + dvdx * dndv,
+                dndy: dudy * dndu + dvdy * dndv,
+                dudx: if dudx.is_finite() { dudx } else { 0.0 },
+                dvdx: if dvdx.is_finite() { dvdx } else { 0.0 },
+                dudy: if dudy.is_finite() { dudy } else { 0.0 },
+                dvdy: if dvdy.is_finite() { dvdy } else { 0.0 },
+            });
+        }
+
+        // Differentials of a perfect mirror bounce in direction
+        pub fn reflect_differentials(&self, incoming_ray: &ray::Ray, direction: Vector3) -> Option<RayDifferential> {
+            let rd = incoming_ray.differentials()?;
+            let sd = self.differentials?;
+
+            let wo = -1.0 * incoming_ray.direction();
+            let dwodx = -1.0 * rd.rx_direction - wo;
+            let dwody = -1.0 * rd.ry_direction - wo;
+
+            let ddndx = dwodx.dot(self.normal) + wo.dot(sd.dndx);
+            let ddndy = dwody.dot(self.normal) + wo.dot(sd.dndy);
+
+            let wo_dot_n = wo.dot(self.normal);
+
+            return Some(RayDifferential {
+                rx_origin: self.point + sd.dpdx,
+                rx_direction: direction - dwodx + 2.0 * (wo_dot_n * sd.dndx + ddndx * self.normal),
+                ry_origin: self.point + sd.dpdy,
+                ry_direction: direction - dwody + 2.0 * (wo_dot_n * sd.dndy + ddndy * self.normal),
+            });
+        }
+
+        // Differentials of a refraction in direction, eta is incident over transmitted
+        pub fn refract_differentials(&self, incoming_ray: &ray::Ray, direction: Vector3, eta: f32) -> Option<RayDifferential> {
+            let rd = incoming_ray.differentials()?;
+            let sd = self.differentials?;
+
+            let wo = -1.0 * incoming_ray.direction();
+            let dwodx = -1.0 * rd.rx_direction - wo;
+            let dwody = -1.0 * rd.ry_direction - wo;
+
+            let ddndx = dwodx.dot(self.normal) + wo.dot(sd.dndx);
+            let ddndy = dwody.dot(self.normal) + wo.dot(sd.dndy);
+
+            let wo_dot_n = wo.dot(self.normal);
+            let wi_dot_n = direction.dot(self.normal).abs();
+            let mu = eta * wo_dot_n - wi_dot_n;
+            let dmu = eta - (eta * eta * wo_dot_n) / wi_dot_n;
+
+            return Some(RayDifferential {
+                rx_origin: self.point + sd.dpdx,
+                rx_direction: direction - eta * dwodx + (mu * sd.dndx + (dmu * ddndx) * self.normal),
+                ry_origin: self.point + sd.dpdy,
+                ry_direction: direction - eta * dwody + (mu * sd.dndy + (dmu * ddndy) * self.normal),
+            });
         }
     }
 
     pub trait SceneObject: Sync + Send {
         fn hit(&self, ray: &ray::Ray, ray_t: Interval) -> Option<HitRecord>;
+
+        // Fraction of light that makes it along the ray, surfaces block it completely
+        fn transmittance(&self, ray: &ray::Ray, ray_t: Interval) -> f32 {
+            return if self.hit(ray, ray_t).is_some() { 0.0 } else { 1.0 };
+        }
+
+        // Uniform point on the surface with the outward normal, for area lights
+        fn sample_surface(&self) -> Option<HitRecord> {
+            return None;
+        }
+
+        fn area(&self) -> f32 {
+            return 0.0;
+        }
+
+        // Axis aligned box around the object, min then max
+        fn bounds(&self) -> Option<(Vector3, Vector3)> {
+            return None;
+        }
     }
-}
\ No newline at end of file
+}
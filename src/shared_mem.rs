@@ -21,4 +21,33 @@ pub struct SharedMem {
     pub texture_changed: u32,
     pub settings_changed: u32,
     pub busy: u32,
+
+    // Stereo settings
+    // 0 = mono, 1 = side-by-side, 2 = top-bottom, 3 = omni-directional equirect (top-bottom)
+    pub stereo_mode: u32,
+    pub interpupillary_distance: f32,
+    // 0 converges at the look at point
+    pub convergence_distance: f32,
+
+    // Global fog, density 0 turns it off
+    pub fog_density: f32,
+    pub fog_albedo_r: f32,
+    pub fog_albedo_g: f32,
+    pub fog_albedo_b: f32,
+    pub fog_anisotropy: f32,
+
+    // Integrator settings
+    // 0 = path tracing, 1 = normals, 2 = depth, 3 = albedo, 4 = UV, 5 = object ID, 6 = ambient occlusion, 7 = bidirectional, 8 = photon mapping, 9 = Metropolis
+    pub integrator: u32,
+    // Depth shown as white by the depth integrator, 0 uses the distance to the look at point
+    pub depth_range: f32,
+    // Occluders further away than the radius don't count
+    pub ao_radius: f32,
+    pub ao_samples: u32,
+    // Photons per pass and the starting gather radius for photon mapping, which shrinks every pass
+    pub photon_count: u32,
+    pub photon_radius: f32,
+
+    // 0 = independent, 1 = stratified, 2 = Halton, 3 = Owen scrambled Sobol, 4 = blue noise
+    pub sampler: u32,
 }
\ No newline at end of file
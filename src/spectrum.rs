@@ -0,0 +1,160 @@
+// Just enough spectral rendering for dispersion
+// Paths stay RGB until they hit something dispersive, then they pick a hero wavelength
+// and carry only that from there on. Integrators weight what the path adds to the film by the
+// wavelength's colour, so it still averages to RGB
+use std::sync::OnceLock;
+
+use crate::vector3::Vector3;
+
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+
+// Uniform over the visible range, u is in [0, 1]
+pub fn sample_wavelength(u: f32) -> f32 {
+    return LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN);
+}
+
+// Piecewise gaussian fit of the CIE 1931 matching functions, Wyman et al. 2013
+fn cie_xyz(lambda: f32) -> Vector3 {
+    let g = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        f32::exp(-0.5 * t * t)
+    };
+
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+
+    return Vector3::new(x, y, z);
+}
+
+// Linear sRGB, clamped since saturated wavelengths are outside the gamut and a negative weight
+// would take light away from the film
+fn unnormalised_rgb(lambda: f32) -> Vector3 {
+    let xyz = cie_xyz(lambda);
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+
+    return Vector3::new(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    );
+}
+
+// Average colour over the visible range, so a uniform wavelength averages to white
+fn rgb_normalisation() -> Vector3 {
+    static NORMALISATION: OnceLock<Vector3> = OnceLock::new();
+
+    return *NORMALISATION.get_or_init(|| {
+        let steps = 340;
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+
+        for i in 0..steps {
+            sum += unnormalised_rgb(sample_wavelength((i as f32 + 0.5) / steps as f32));
+        }
+
+        return sum / steps as f32;
+    });
+}
+
+// Film weight for a path that carries only this wavelength, never negative
+pub fn wavelength_to_rgb(lambda: f32) -> Vector3 {
+    return unnormalised_rgb(lambda) / rgb_normalisation();
+}
+
+// Wavelength dependent refraction index
+#[derive(Clone, Copy)]
+pub enum IorModel {
+    Constant(f32),
+    // n = a + b / lambda^2, lambda in micrometres
+    Cauchy { a: f32, b: f32 },
+    // n^2 = 1 + sum(b * lambda^2 / (lambda^2 - c)), lambda in micrometres
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl IorModel {
+    pub const BK7: IorModel = IorModel::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    pub const DENSE_FLINT: IorModel = IorModel::Sellmeier {
+        b: [1.737_596_9, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_3],
+    };
+
+    // None if the index drops below 1 anywhere in the visible range, a = b = 0 would give 0
+    pub fn cauchy(a: f32, b: f32) -> Option<Self> {
+        let model = IorModel::Cauchy { a, b };
+        if model.at(LAMBDA_MIN) < 1.0 || model.at(LAMBDA_MAX) < 1.0 {
+            return None;
+        }
+
+        return Some(model);
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        return !matches!(self, IorModel::Constant(_));
+    }
+
+    // Index at the middle of the visible range, for paths that haven't picked a wavelength
+    pub fn nominal(&self) -> f32 {
+        return self.at(550.0);
+    }
+
+    pub fn at(&self, lambda_nm: f32) -> f32 {
+        let lambda = lambda_nm / 1000.0;
+        let lambda2 = lambda * lambda;
+
+        return match self {
+            IorModel::Constant(n) => *n,
+            IorModel::Cauchy { a, b } => a + b / lambda2,
+            IorModel::Sellmeier { b, c } => {
+                let n2: f32 = b.iter().zip(c).map(|(b, c)| b * lambda2 / (lambda2 - c)).sum();
+                f32::sqrt(1.0 + n2)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sellmeier_matches_catalogue_values() {
+        // Helium d line
+        assert!((IorModel::BK7.at(587.56) - 1.5168).abs() < 1e-4);
+        assert!((IorModel::DENSE_FLINT.at(587.56) - 1.7847).abs() < 1e-3);
+
+        // Normal dispersion, blue bends more
+        assert!(IorModel::BK7.at(450.0) > IorModel::BK7.at(650.0));
+    }
+
+    #[test]
+    fn cauchy_follows_its_formula() {
+        let model = IorModel::cauchy(1.5, 0.005).unwrap();
+        assert!((model.at(500.0) - (1.5 + 0.005 / 0.25)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cauchy_below_one_is_rejected() {
+        assert!(IorModel::cauchy(0.0, 0.0).is_none());
+        assert!(IorModel::cauchy(1.0, -0.01).is_none());
+        assert!(IorModel::cauchy(1.0, 0.0).is_some());
+    }
+
+    #[test]
+    fn film_weights_are_positive_and_average_to_white() {
+        let steps = 340;
+        let mut sum = Vector3::new(0.0, 0.0, 0.0);
+
+        for i in 0..steps {
+            let weight = wavelength_to_rgb(sample_wavelength((i as f32 + 0.5) / steps as f32));
+            assert!(weight.x() >= 0.0 && weight.y() >= 0.0 && weight.z() >= 0.0);
+            sum += weight;
+        }
+
+        assert!((sum / steps as f32 - Vector3::new(1.0, 1.0, 1.0)).norm() < 1e-3);
+    }
+}
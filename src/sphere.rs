@@ -1,4 +1,5 @@
 pub mod sphere {
+    use core::f32;
     use std::sync::Arc;
 
     use crate::interval::Interval;
@@ -7,6 +8,7 @@ pub mod sphere {
     use crate::ray::ray;
     use crate::scene_object::scene_object::SceneObject;
     use crate::vector3::Vector3;
+    use crate::vector_utils::random_vec3_unit;
 
     pub struct Sphere {
         centre: Vector3,
@@ -22,6 +24,31 @@ pub mod sphere {
                 material,
             }
         }
+
+        // UVs from a point on the unit sphere
+        // u goes around the Y axis starting at -X, v goes from -Y to +Y
+        fn get_sphere_uv(point: Vector3) -> (f32, f32) {
+            let theta = f32::acos(-point.y());
+            let phi = f32::atan2(-point.z(), point.x()) + f32::consts::PI;
+
+            return (phi / (2.0 * f32::consts::PI), theta / f32::consts::PI);
+        }
+
+        // Partial derivatives of the hit point along u and v, from the mapping above
+        fn get_sphere_dpduv(&self, point: Vector3) -> (Vector3, Vector3) {
+            let (x, y, z) = (point.x(), point.y(), point.z());
+            let dpdu = (2.0 * f32::consts::PI * self.radius) * Vector3::new(z, 0.0, -x);
+
+            let sin_theta = f32::sqrt(x * x + z * z);
+            // Poles, v still moves along the surface but u doesn't
+            if sin_theta < 1e-6 {
+                return (dpdu, (f32::consts::PI * self.radius) * Vector3::new(1.0, 0.0, 0.0));
+            }
+
+            let dpdv = (f32::consts::PI * self.radius) * Vector3::new(-y * x / sin_theta, sin_theta, -y * z / sin_theta);
+
+            return (dpdu, dpdv);
+        }
     }
 
     impl SceneObject for Sphere {
@@ -55,9 +82,75 @@ pub mod sphere {
             let outward_normal = (point - self.centre) / self.radius;
             let front_face = ray.direction().dot(outward_normal) < 0.0;
             let normal = if front_face { outward_normal } else { -1.0 * outward_normal };
+            let (u, v) = Self::get_sphere_uv(outward_normal);
             let material = self.material.clone();
 
-            return Some(HitRecord { point, normal, material, t, front_face });
+            let (dpdu, dpdv) = self.get_sphere_dpduv(outward_normal);
+
+            // n = (p - c) / r, flipped along with the normal
+            let sign = if front_face { 1.0 } else { -1.0 };
+            let dndu = (sign / self.radius) * dpdu;
+            let dndv = (sign / self.radius) * dpdv;
+
+            let mut hit_record = HitRecord { point, normal, material, t, u, v, front_face, dpdu, dpdv, dndu, dndv, differentials: None, object_id: 0 };
+
+            if ray.differentials().is_some() {
+                hit_record.compute_differentials(ray, dpdu, dpdv, dndu, dndv);
+            }
+
+            return Some(hit_record);
+        }
+
+        fn sample_surface(&self) -> Option<HitRecord> {
+            let outward_normal = random_vec3_unit();
+            let (u, v) = Self::get_sphere_uv(outward_normal);
+            let (dpdu, dpdv) = self.get_sphere_dpduv(outward_normal);
+
+            let mut hit_record = HitRecord::new(self.material.clone());
+            hit_record.point = self.centre + self.radius * outward_normal;
+            hit_record.normal = outward_normal;
+            hit_record.u = u;
+            hit_record.v = v;
+            hit_record.front_face = true;
+            hit_record.dpdu = dpdu;
+            hit_record.dpdv = dpdv;
+            hit_record.dndu = dpdu / self.radius;
+            hit_record.dndv = dpdv / self.radius;
+
+            return Some(hit_record);
+        }
+
+        fn area(&self) -> f32 {
+            return 4.0 * f32::consts::PI * self.radius * self.radius;
+        }
+
+        fn bounds(&self) -> Option<(Vector3, Vector3)> {
+            let extent = Vector3::new(self.radius, self.radius, self.radius);
+            return Some((self.centre - extent, self.centre + extent));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{material::Lambertian, scene_object::scene_object::SceneObject, vector3::Vector3};
+
+    use super::sphere::Sphere;
+
+    #[test]
+    fn surface_samples_lie_on_the_sphere() {
+        let centre = Vector3::new(1.0, -2.0, 0.5);
+        let sphere = Sphere::new(centre, 1.5, Arc::new(Lambertian::new(Vector3::new(0.5, 0.5, 0.5))));
+
+        for _ in 0..1000 {
+            let hit = sphere.sample_surface().unwrap();
+
+            // Only xyz, so a stray w lane can't make up the length
+            let offset = hit.point - centre;
+            assert!((Vector3::new(offset.x(), offset.y(), offset.z()).norm() - 1.5).abs() < 1e-4);
+            assert!((Vector3::new(hit.normal.x(), hit.normal.y(), hit.normal.z()).norm() - 1.0).abs() < 1e-5);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,371 @@
+// Textures are sampled by materials with the UVs from the hit record
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{perlin::Perlin, rng::Xorshift32State, scene_object::scene_object::SurfaceDifferentials, vector3::Vector3};
+
+pub trait Texture: Sync + Send {
+    fn value(&self, u: f32, v: f32, point: Vector3) -> Vector3;
+
+    // Lookup over the footprint of the hit, only image textures need to filter
+    fn value_filtered(&self, u: f32, v: f32, point: Vector3, _differentials: Option<&SurfaceDifferentials>) -> Vector3 {
+        return self.value(u, v, point);
+    }
+}
+
+pub struct SolidColor {
+    albedo: Vector3
+}
+
+impl SolidColor {
+    pub fn new(albedo: Vector3) -> Self {
+        return Self { albedo };
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _point: Vector3) -> Vector3 {
+        return self.albedo;
+    }
+}
+
+// One level of the mip pyramid
+struct MipLevel {
+    width: u32,
+    height: u32,
+    // Linear RGB, top row first
+    pixels: Vec<Vector3>,
+}
+
+impl MipLevel {
+    // Clamps to the edge
+    fn texel(&self, i: i32, j: i32) -> Vector3 {
+        let i = i.clamp(0, self.width as i32 - 1) as u32;
+        let j = j.clamp(0, self.height as i32 - 1) as u32;
+
+        return self.pixels[(j * self.width + i) as usize];
+    }
+
+    fn bilinear(&self, u: f32, v: f32) -> Vector3 {
+        // Texel centres are at half coordinates
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let dx = x - x0;
+        let dy = y - y0;
+        let (i, j) = (x0 as i32, y0 as i32);
+
+        return ((1.0 - dx) * (1.0 - dy)) * self.texel(i, j)
+             + (dx * (1.0 - dy)) * self.texel(i + 1, j)
+             + ((1.0 - dx) * dy) * self.texel(i, j + 1)
+             + (dx * dy) * self.texel(i + 1, j + 1);
+    }
+
+    // 2x2 box filter, odd sizes repeat the last row or column
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize);
+
+        for j in 0..height as i32 {
+            for i in 0..width as i32 {
+                let sum = self.texel(2 * i, 2 * j) + self.texel(2 * i + 1, 2 * j)
+                        + self.texel(2 * i, 2 * j + 1) + self.texel(2 * i + 1, 2 * j + 1);
+                pixels.push(0.25 * sum);
+            }
+        }
+
+        return Self { width, height, pixels };
+    }
+}
+
+pub struct ImageTexture {
+    // Level 0 is the full image
+    levels: Vec<MipLevel>,
+}
+
+impl ImageTexture {
+    // Takes RGBA bytes as they come from a canvas, alpha is ignored
+    pub fn from_rgba(width: u32, height: u32, data: &[u8]) -> Self {
+        return Self::from_bytes(width, height, data, true);
+    }
+
+    // Same but for normal and height maps, the values are used as they are
+    pub fn from_rgba_data(width: u32, height: u32, data: &[u8]) -> Self {
+        return Self::from_bytes(width, height, data, false);
+    }
+
+    fn from_bytes(width: u32, height: u32, data: &[u8], gamma_decode: bool) -> Self {
+        let pixels: Vec<Vector3> = data.chunks_exact(4)
+            .take(width as usize * height as usize)
+            .map(|pixel| {
+                let r = pixel[0] as f32 / 255.0;
+                let g = pixel[1] as f32 / 255.0;
+                let b = pixel[2] as f32 / 255.0;
+
+                // Undo the gamma 2 we apply on output
+                if gamma_decode {
+                    Vector3::new(r * r, g * g, b * b)
+                }
+
+                else {
+                    Vector3::new(r, g, b)
+                }
+            })
+            .collect();
+
+        if width == 0 || height == 0 || pixels.len() < width as usize * height as usize {
+            return Self { levels: Vec::new() };
+        }
+
+        let mut levels = vec![MipLevel { width, height, pixels }];
+        while levels.last().unwrap().width > 1 || levels.last().unwrap().height > 1 {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+
+        return Self { levels };
+    }
+
+    // Trilinear between the two closest levels
+    fn lookup(&self, u: f32, v: f32, level: f32) -> Vector3 {
+        let last = (self.levels.len() - 1) as f32;
+        let level = level.clamp(0.0, last);
+        let lower = level.floor();
+        let delta = level - lower;
+
+        let value = self.levels[lower as usize].bilinear(u, v);
+        if delta == 0.0 {
+            return value;
+        }
+
+        return (1.0 - delta) * value + delta * self.levels[lower as usize + 1].bilinear(u, v);
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: Vector3) -> Vector3 {
+        // Magenta is easy to spot
+        if self.levels.is_empty() {
+            return Vector3::new(1.0, 0.0, 1.0);
+        }
+
+        // Image rows go top to bottom, v goes bottom to top
+        return self.lookup(u.clamp(0.0, 1.0), 1.0 - v.clamp(0.0, 1.0), 0.0);
+    }
+
+    fn value_filtered(&self, u: f32, v: f32, point: Vector3, differentials: Option<&SurfaceDifferentials>) -> Vector3 {
+        let Some(sd) = differentials else {
+            return self.value(u, v, point);
+        };
+
+        if self.levels.is_empty() {
+            return Vector3::new(1.0, 0.0, 1.0);
+        }
+
+        // Footprint in texels of the full image, one texel maps to level 0
+        let size = self.levels[0].width.max(self.levels[0].height) as f32;
+        let width = size * sd.dudx.abs().max(sd.dvdx.abs()).max(sd.dudy.abs()).max(sd.dvdy.abs());
+        let level = width.max(1e-8).log2();
+
+        return self.lookup(u.clamp(0.0, 1.0), 1.0 - v.clamp(0.0, 1.0), level);
+    }
+}
+
+// Checker size is in world units
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct CheckerParams {
+    pub scale: f32,
+    pub even_r: f32,
+    pub even_g: f32,
+    pub even_b: f32,
+    pub odd_r: f32,
+    pub odd_g: f32,
+    pub odd_b: f32,
+}
+
+#[wasm_bindgen]
+impl CheckerParams {
+    // Unit checks in white and dark grey
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        return Self {
+            scale: 1.0,
+            even_r: 0.9,
+            even_g: 0.9,
+            even_b: 0.9,
+            odd_r: 0.2,
+            odd_g: 0.2,
+            odd_b: 0.2,
+        };
+    }
+}
+
+impl Default for CheckerParams {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// 3D checker in world space, so it doesn't stretch at the poles like UVs would
+pub struct CheckerTexture {
+    inv_scale: f32,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f32, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        return Self { inv_scale: 1.0 / scale, even, odd };
+    }
+
+    pub fn from_colors(scale: f32, even: Vector3, odd: Vector3) -> Self {
+        return Self::new(scale, Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)));
+    }
+
+    pub fn from_params(params: &CheckerParams) -> Self {
+        let even = Vector3::new(params.even_r, params.even_g, params.even_b);
+        let odd = Vector3::new(params.odd_r, params.odd_g, params.odd_b);
+
+        return Self::from_colors(params.scale, even, odd);
+    }
+}
+
+impl CheckerTexture {
+    fn pick(&self, point: Vector3) -> &Arc<dyn Texture> {
+        let x = (self.inv_scale * point.x()).floor() as i32;
+        let y = (self.inv_scale * point.y()).floor() as i32;
+        let z = (self.inv_scale * point.z()).floor() as i32;
+
+        if (x + y + z) % 2 == 0 {
+            return &self.even;
+        }
+
+        return &self.odd;
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, point: Vector3) -> Vector3 {
+        return self.pick(point).value(u, v, point);
+    }
+
+    fn value_filtered(&self, u: f32, v: f32, point: Vector3, differentials: Option<&SurfaceDifferentials>) -> Vector3 {
+        return self.pick(point).value_filtered(u, v, point, differentials);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    Noise,
+    Turbulence,
+    Marble,
+}
+
+impl NoiseKind {
+    pub fn from_u32(kind: u32) -> Self {
+        return match kind {
+            1 => NoiseKind::Turbulence,
+            2 => NoiseKind::Marble,
+            _ => NoiseKind::Noise,
+        };
+    }
+}
+
+pub struct NoiseTexture {
+    noise: Perlin,
+    kind: NoiseKind,
+    scale: f32,
+    albedo: Vector3,
+}
+
+impl NoiseTexture {
+    pub fn new(kind: NoiseKind, scale: f32, albedo: Vector3, seed: u32) -> Self {
+        let mut rng = Xorshift32State::seeded(seed);
+        return Self { noise: Perlin::new(&mut rng), kind, scale, albedo };
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: Vector3) -> Vector3 {
+        let factor = match self.kind {
+            // Noise is in [-1, 1]
+            NoiseKind::Noise => 0.5 * (1.0 + self.noise.noise(self.scale * point)),
+            NoiseKind::Turbulence => self.noise.turbulence(self.scale * point, 7),
+            // Turbulence shifts the phase of the stripes
+            NoiseKind::Marble => 0.5 * (1.0 + f32::sin(self.scale * point.z() + 10.0 * self.noise.turbulence(point, 7))),
+        };
+
+        return factor * self.albedo;
+    }
+}
+
+// Texture times a colour, like glTF's base colour factor
+pub struct ScaledTexture {
+    texture: Arc<dyn Texture>,
+    scale: Vector3,
+}
+
+impl ScaledTexture {
+    pub fn new(texture: Arc<dyn Texture>, scale: Vector3) -> Self {
+        return Self { texture, scale };
+    }
+}
+
+impl Texture for ScaledTexture {
+    fn value(&self, u: f32, v: f32, point: Vector3) -> Vector3 {
+        return self.texture.value(u, v, point).component_mul(self.scale);
+    }
+
+    fn value_filtered(&self, u: f32, v: f32, point: Vector3, differentials: Option<&SurfaceDifferentials>) -> Vector3 {
+        return self.texture.value_filtered(u, v, point, differentials).component_mul(self.scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vector3, b: Vector3) -> bool {
+        return (a - b).norm() < 1e-5;
+    }
+
+    #[test]
+    fn mip_chain_goes_down_to_one_texel() {
+        let texture = ImageTexture::from_rgba_data(8, 2, &[255; 8 * 2 * 4]);
+        let sizes: Vec<(u32, u32)> = texture.levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, vec![(8, 2), (4, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn downsample_keeps_the_average() {
+        let pixels = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.5, 0.5, 0.5)];
+        let level = MipLevel { width: 2, height: 2, pixels }.downsample();
+        assert_eq!((level.width, level.height), (1, 1));
+        assert!(close(level.pixels[0], Vector3::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn bilinear_at_texel_centres_is_exact() {
+        let pixels = vec![Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0), Vector3::new(1.0, 1.0, 1.0)];
+        let level = MipLevel { width: 2, height: 2, pixels: pixels.clone() };
+        assert!(close(level.bilinear(0.25, 0.25), pixels[0]));
+        assert!(close(level.bilinear(0.75, 0.25), pixels[1]));
+        assert!(close(level.bilinear(0.25, 0.75), pixels[2]));
+        // Halfway between all four
+        assert!(close(level.bilinear(0.5, 0.5), Vector3::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn gamma_is_only_decoded_for_colour() {
+        let data = [128, 128, 128, 255];
+        let color = ImageTexture::from_rgba(1, 1, &data).value(0.5, 0.5, Vector3::default());
+        let raw = ImageTexture::from_rgba_data(1, 1, &data).value(0.5, 0.5, Vector3::default());
+        let c = 128.0 / 255.0;
+        assert!(close(color, Vector3::new(c * c, c * c, c * c)));
+        assert!(close(raw, Vector3::new(c, c, c)));
+    }
+}
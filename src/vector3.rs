@@ -1,6 +1,6 @@
 // It is in fact not unused, WASM SIMD requires unsafe. Compiler is worong here.
 #![allow(unused_unsafe)]
-use std::{arch::wasm32::{f32x4, f32x4_add, f32x4_div, f32x4_eq, f32x4_extract_lane, f32x4_mul, f32x4_replace_lane, f32x4_splat, f32x4_sqrt, f32x4_sub, i32x4_shuffle, v128}, f32::NAN};
+use std::arch::wasm32::{f32x4, f32x4_add, f32x4_div, f32x4_eq, f32x4_extract_lane, f32x4_mul, f32x4_replace_lane, f32x4_splat, f32x4_sqrt, f32x4_sub, i32x4_bitmask, i32x4_shuffle, v128};
 
 #[derive(Clone, Copy)]
 #[derive(Debug)]
@@ -186,12 +186,32 @@ impl Default for Vector3 {
 impl PartialEq for Vector3 {
     fn eq(&self, other: &Self) -> bool {
         let equality_vec = unsafe { f32x4_eq(self.0, other.0) };
-        let eq0 = unsafe { f32x4_extract_lane::<0>(equality_vec) };
-        let eq1 = unsafe { f32x4_extract_lane::<1>(equality_vec) };
-        let eq2 = unsafe { f32x4_extract_lane::<2>(equality_vec) };
-        let eq3 = unsafe { f32x4_extract_lane::<3>(equality_vec) };
 
-        // all 1s, so it will be NAN
-        return !(eq0 == NAN || eq1 == NAN || eq2 == NAN || eq3 == NAN);
+        // Lanes that match are all 1s, which as a float is NAN and never equal to anything
+        // So check the sign bits instead, the fourth lane is padding
+        return i32x4_bitmask(equality_vec) & 0b111 == 0b111;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equality_checks_every_component() {
+        // Used to compare the lane masks against NAN, which made every pair equal
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert!(v == Vector3::new(1.0, 2.0, 3.0));
+        assert!(v != Vector3::new(0.0, 2.0, 3.0));
+        assert!(v != Vector3::new(1.0, 0.0, 3.0));
+        assert!(v != Vector3::new(1.0, 2.0, 0.0));
+        assert!(Vector3::default() == Vector3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn equality_ignores_the_padding() {
+        // Zero over zero leaves NAN in the fourth lane
+        let infinite = Vector3::new(1.0, 1.0, 1.0) / 0.0;
+        assert!(infinite == Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY));
+    }
+}
@@ -14,7 +14,9 @@ pub fn random_vec3_range(min: f32, max: f32) -> Vector3 {
 
     vec = unsafe { f32x4_mul(vec, range) };
     vec = unsafe { f32x4_add(vec, min) };
-    return Vector3(vec);
+
+    // w has to stay 0, norm and dot add it in
+    return Vector3::from_v128(vec);
 }
 
 #[inline(always)]
@@ -56,7 +58,7 @@ pub fn random_vec3_hemisphere(normal: Vector3) -> Vector3 {
 #[inline(always)]
 pub fn near_zero(vector: Vector3) -> bool {
     let s = 1e-5;
-    return vector.x() < s && vector.y() < s && vector.z() < s;
+    return vector.x().abs() < s && vector.y().abs() < s && vector.z().abs() < s;
 }
 
 #[inline(always)]
@@ -70,4 +72,128 @@ pub fn refract(uv: Vector3, n: Vector3, etai_over_etat: f32) -> Vector3 {
     let r_out_perp = etai_over_etat * (uv + cost_theta * n);
     let r_out_parallel = -f32::sqrt(f32::abs(1.0 - r_out_perp.norm_squared())) * n;
     return r_out_perp + r_out_parallel;
-}
\ No newline at end of file
+}
+
+// Orthonormal basis, w is the normal
+#[derive(Clone, Copy)]
+pub struct Onb {
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3,
+}
+
+impl Onb {
+    // Any basis around the normal, branchless version from Duff et al. 2017
+    pub fn new(normal: Vector3) -> Self {
+        let sign = 1.0f32.copysign(normal.z());
+        let a = -1.0 / (sign + normal.z());
+        let b = normal.x() * normal.y() * a;
+
+        let u = Vector3::new(1.0 + sign * normal.x() * normal.x() * a, sign * b, -sign * normal.x());
+        let v = Vector3::new(b, sign + normal.y() * normal.y() * a, -normal.y());
+
+        return Self { u, v, w: normal };
+    }
+
+    // Basis with u following the tangent as close as possible
+    pub fn from_tangent(normal: Vector3, tangent: Vector3) -> Self {
+        // Gram-Schmidt
+        let u = tangent - normal.dot(tangent) * normal;
+        if u.norm_squared() < 1e-12 {
+            return Self::new(normal);
+        }
+
+        let u = u.normalize();
+        return Self { u, v: normal.cross(u), w: normal };
+    }
+
+    #[inline(always)]
+    pub fn to_world(&self, local: Vector3) -> Vector3 {
+        return local.x() * self.u + local.y() * self.v + local.z() * self.w;
+    }
+
+    #[inline(always)]
+    pub fn to_local(&self, world: Vector3) -> Vector3 {
+        return Vector3::new(world.dot(self.u), world.dot(self.v), world.dot(self.w));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_orthonormal(onb: &Onb) {
+        for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+            assert!(a.dot(b).abs() < 1e-5);
+        }
+
+        for axis in [onb.u, onb.v, onb.w] {
+            assert!((axis.norm() - 1.0).abs() < 1e-5);
+        }
+
+        // Right handed
+        assert!((onb.u.cross(onb.v) - onb.w).norm() < 1e-5);
+    }
+
+    #[test]
+    fn random_unit_vectors_are_unit_in_xyz() {
+        // A random w lane used to take its share of the length
+        for _ in 0..1000 {
+            let v = random_vec3_unit();
+            assert!((Vector3::new(v.x(), v.y(), v.z()).norm() - 1.0).abs() < 1e-5);
+            assert_eq!(v.w(), 0.0);
+        }
+    }
+
+    #[test]
+    fn near_zero_works_on_both_signs() {
+        // Negative components used to pass, so Lambertian threw away valid scatter directions
+        assert!(near_zero(Vector3::new(1e-6, -1e-6, 0.0)));
+        assert!(!near_zero(Vector3::new(-0.5, -0.5, -0.5)));
+        assert!(!near_zero(Vector3::new(0.0, 0.0, 0.5)));
+    }
+
+    #[test]
+    fn basis_is_orthonormal() {
+        // Includes both sides of the branchless sign flip
+        for normal in [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0), Vector3::new(1.0, 2.0, -3.0).normalize(), Vector3::new(-0.3, 0.1, 0.9).normalize()] {
+            assert_orthonormal(&Onb::new(normal));
+        }
+    }
+
+    #[test]
+    fn tangent_basis_follows_the_tangent() {
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let onb = Onb::from_tangent(normal, Vector3::new(2.0, 0.5, 0.0));
+        assert_orthonormal(&onb);
+        assert!((onb.u - Vector3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+
+        // Tangent along the normal falls back to any basis
+        assert_orthonormal(&Onb::from_tangent(normal, normal));
+    }
+
+    #[test]
+    fn local_and_world_round_trip() {
+        let onb = Onb::new(Vector3::new(0.6, -0.8, 0.0));
+        let v = Vector3::new(0.3, -1.2, 2.5);
+        assert!((onb.to_world(onb.to_local(v)) - v).norm() < 1e-5);
+    }
+
+    #[test]
+    fn refraction_follows_snell() {
+        let incoming = Vector3::new(0.6, -0.8, 0.0);
+        let normal = Vector3::new(0.0, 1.0, 0.0);
+        let refracted = refract(incoming, normal, 1.0 / 1.5);
+
+        // sin theta_t = sin theta_i / 1.5, and it stays a unit vector
+        assert!((refracted.x() - 0.6 / 1.5).abs() < 1e-5);
+        assert!((refracted.norm() - 1.0).abs() < 1e-5);
+        assert!(refracted.y() < 0.0);
+    }
+
+    #[test]
+    fn reflection_keeps_the_tangent_part() {
+        let reflected = reflect(Vector3::new(0.6, -0.8, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        assert!((reflected - Vector3::new(0.6, 0.8, 0.0)).norm() < 1e-6);
+    }
+}